@@ -7,7 +7,9 @@ use std::{
 use regex::Regex;
 use tokio::{self};
 
-use offdictd::{topk::TopkParam, *};
+use offdictd::*;
+use fst_index::fstmmap;
+use index::{Index, IndexKind};
 use topk::Strprox;
 
 const REG: &str = r"(?<query>[^+]+)\s*([+](?<num>[0-9]+))?";
@@ -18,6 +20,7 @@ async fn main() -> Result<()> {
     let db_path = PathBuf::from(conf.data_path.clone());
     let fst = fstmmap::load_file(&fstmmap::path(&db_path))?;
     let strp = Strprox::load_file(&Strprox::path(&db_path))?;
+    let hybrid = Index::load_kind(&Index::path(&db_path), IndexKind::Hybrid)?;
     let lineparam = Regex::new(REG)?;
     loop {
         let li = readline().await.unwrap();
@@ -33,8 +36,10 @@ async fn main() -> Result<()> {
                     } else {
                         1
                     };
-                    println!("fst: {:?}", fst.query(q, false)?);
-                    println!("meta: {:?}", strp.query(q, TopkParam::new(n))?);
+                    let opts = SearchOptions::top(n);
+                    println!("fst: {:?}", fst.query(q, &opts)?);
+                    println!("meta: {:?}", strp.query(q, &opts)?);
+                    println!("hybrid: {:?}", hybrid.query(q, &opts)?);
                 }
             }
         }
@@ -0,0 +1,207 @@
+use std::fs;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::bail;
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::properties;
+
+use crate::*;
+
+pub const BACKUP_MANIFEST: &str = "backup.yaml";
+
+/// Suffix for what a restore moved out of the way
+const REPLACED: &str = "replaced";
+/// Where a restore copies the backup before moving it into place, inside the data directory
+const STAGING: &str = "restore.tmp";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackupManifest {
+    pub version: String,
+    /// Seconds since the unix epoch
+    pub created: u64,
+    /// File name of the index, if there was one
+    pub index: Option<String>,
+    /// Other files of the index
+    #[serde(default)]
+    pub index_extra: Vec<String>,
+    /// Estimated by RocksDB
+    pub entries: Option<u64>,
+    /// Whether the user overlay is included
+    #[serde(default)]
+    pub overlay: bool,
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// Checkpoints the database into `to`. Safe to call while serving.
+    pub fn backup(&self, to: &Path) -> Result<BackupManifest> {
+        if to.join(DBPATH).exists() {
+            bail!("{:?} already holds a backup", to);
+        }
+        create_dir_all(to)?;
+        let entries = {
+            let db = self.db.read().unwrap();
+            Checkpoint::new(&db)?.create_checkpoint(to.join(DBPATH))?;
+            db.property_int_value(properties::ESTIMATE_NUM_KEYS)?
+        };
+        {
+            let user = self.user.read().unwrap();
+            Checkpoint::new(&user)?.create_checkpoint(to.join(USERDB))?;
+        }
+
+        let idx = Ix::path(&self.dirpath);
+        let index = if idx.exists() {
+            fs::copy(&idx, to.join(Ix::FILE_NAME))?;
+            Some(Ix::FILE_NAME.to_owned())
+        } else {
+            None
+        };
+        let mut index_extra = vec![];
+        for f in Ix::EXTRA_FILES {
+            if self.dirpath.join(f).exists() {
+                fs::copy(self.dirpath.join(f), to.join(f))?;
+                index_extra.push(f.to_string());
+            }
+        }
+
+        let manifest = BackupManifest {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            created: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            index,
+            index_extra,
+            entries,
+            overlay: true,
+        };
+        serde_yaml::to_writer(File::create(to.join(BACKUP_MANIFEST))?, &manifest)?;
+
+        Ok(manifest)
+    }
+}
+
+/// Checks that every record in the backup decodes and the index loads
+pub fn validate_backup<Ix: Indexer>(from: &Path) -> Result<BackupManifest> {
+    let manifest: BackupManifest =
+        serde_yaml::from_reader(File::open(from.join(BACKUP_MANIFEST))?)?;
+    let (opts, _) = db_options(&Default::default());
+    {
+        let db = rocks::open_for_read_only(&opts, from.join(DBPATH), false)?;
+        let mut records = 0;
+        let mut damaged = 0;
+        for r in db.iterator(rocksdb::IteratorMode::Start) {
+            let (k, v) = r?;
+            records += 1;
+            if decode_record(&k, &v).is_err() {
+                damaged += 1;
+            }
+        }
+        if damaged > 0 {
+            bail!(
+                "{} of {} records in the backup are damaged",
+                damaged,
+                records
+            );
+        }
+        println!("{} records check out", records);
+    }
+    if manifest.overlay {
+        let user = rocks::open_for_read_only(&opts, from.join(USERDB), false)?;
+        let n = user.iterator(rocksdb::IteratorMode::Start).count();
+        println!("{} overlay entries", n);
+    }
+    if let Some(index) = &manifest.index {
+        if index != Ix::FILE_NAME {
+            bail!("backup has a {} index, expected {}", index, Ix::FILE_NAME);
+        }
+        Ix::load_file(&from.join(index))?;
+    }
+
+    Ok(manifest)
+}
+
+/// Validates the backup, copies it next to the data, then moves the current data aside
+/// and the copy into place. Refuses while something, like the daemon, has the database open.
+pub fn restore<Ix: Indexer>(from: &Path, data: &Path) -> Result<()> {
+    let manifest = validate_backup::<Ix>(from)?;
+    create_dir_all(data)?;
+    for db in [DBPATH, USERDB] {
+        ensure_closed(&data.join(db))?;
+    }
+
+    let mut items = vec![DBPATH];
+    if manifest.overlay {
+        items.push(USERDB);
+    }
+    items.extend(manifest.index.as_deref());
+    items.extend(manifest.index_extra.iter().map(String::as_str));
+
+    // A failed copy leaves the current data as it was
+    let staging = data.join(STAGING);
+    remove_any(&staging)?;
+    create_dir_all(&staging)?;
+    for item in &items {
+        if let Err(e) = copy_any(&from.join(item), &staging.join(item)) {
+            remove_any(&staging)?;
+            return Err(e.context(format!("copying {} failed, nothing was replaced", item)));
+        }
+    }
+    for item in items {
+        let current = data.join(item);
+        if current.exists() {
+            let aside = data.join(format!("{}.{}", item, REPLACED));
+            remove_any(&aside)?;
+            fs::rename(&current, &aside)?;
+            println!("moved {:?} to {:?}", current, aside);
+        }
+        fs::rename(staging.join(item), &current)?;
+    }
+    remove_any(&staging)?;
+
+    Ok(())
+}
+
+/// RocksDB holds a POSIX record lock on `LOCK` while it's open, asked about without taking it
+fn ensure_closed(db: &Path) -> Result<()> {
+    let lock = db.join("LOCK");
+    if !lock.exists() {
+        return Ok(());
+    }
+    let file = fs::OpenOptions::new().read(true).write(true).open(&lock)?;
+    let mut probe: libc::flock = unsafe { std::mem::zeroed() };
+    probe.l_type = libc::F_WRLCK as _;
+    probe.l_whence = libc::SEEK_SET as _;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut probe) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    if probe.l_type as libc::c_int != libc::F_UNLCK {
+        bail!(
+            "{:?} is open in process {}, stop the daemon first",
+            db,
+            probe.l_pid
+        );
+    }
+    Ok(())
+}
+
+fn remove_any(p: &Path) -> Result<()> {
+    if p.is_dir() {
+        fs::remove_dir_all(p)?;
+    } else if p.exists() {
+        fs::remove_file(p)?;
+    }
+    Ok(())
+}
+
+/// Checkpoints are flat directories
+fn copy_any(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
@@ -7,6 +7,98 @@ use serde::Deserialize;
 pub struct OffdictConfig {
     pub data_path: PathBuf,
     pub hide_on_blur: bool,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Which index answers fuzzy queries
+    #[serde(default)]
+    pub index: IndexKind,
+    /// Extra `form<TAB>lemma` pairs on top of the built-in irregular forms
+    #[serde(default)]
+    pub irregular_forms: Option<PathBuf>,
+    /// Show the target's definition under entries that only point to it, like "see colour"
+    #[serde(default)]
+    pub inline_redirects: bool,
+    /// How many edits a very common word may make up for over a rare one, once `offdictd freq` imported a list.
+    /// 0 leaves frequency to break ties only.
+    #[serde(default = "default_frequency_weight")]
+    pub frequency_weight: f32,
+    /// Rank words looked up before and dictionaries read most higher, from the history in user.db
+    #[serde(default = "default_personalize")]
+    pub personalize: bool,
+    /// Searches whose results are kept for repeated lookups. 0 turns the cache off.
+    #[serde(default = "default_result_cache")]
+    pub result_cache: usize,
+    /// Fuzzy results scoring above this, edits plus the frequency penalty, get "did you mean" suggestions instead.
+    /// Longer queries are allowed one more per 8 chars.
+    #[serde(default = "default_confident_score")]
+    pub confident_score: f32,
+}
+
+fn default_confident_score() -> f32 {
+    crate::suggest::DEFAULT_CONFIDENT_SCORE
+}
+
+fn default_result_cache() -> usize {
+    crate::result_cache::DEFAULT_RESULT_CACHE
+}
+
+fn default_personalize() -> bool {
+    true
+}
+
+pub const DEFAULT_FREQUENCY_WEIGHT: f32 = 1.;
+
+fn default_frequency_weight() -> f32 {
+    DEFAULT_FREQUENCY_WEIGHT
+}
+
+/// RocksDB tuning, the `storage` section
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Per level, starting from L0. The last entry carries over to the remaining levels.
+    pub compression: Vec<Compression>,
+    /// Overrides the bottommost level, where most of the data ends up
+    pub bottommost_compression: Option<Compression>,
+    /// Block cache size in MiB
+    pub block_cache_mb: usize,
+    /// Bits per key for bloom filters. 0 turns them off.
+    pub bloom_bits: f64,
+    /// Disables automatic compactions for faster imports. Leave off when serving.
+    pub bulk_load: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            compression: vec![Compression::None, Compression::None, Compression::Lz4],
+            bottommost_compression: Some(Compression::Zstd),
+            block_cache_mb: 32,
+            bloom_bits: 10.,
+            bulk_load: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexKind {
+    /// Top-k by prefix edit distance
+    #[default]
+    Strprox,
+    /// Exact, prefix and small edit distances over an fst
+    Fst,
+    /// fst exact and prefix hits first, then Strprox for typos
+    Hybrid,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Snappy,
+    Lz4,
+    Zstd,
 }
 
 pub fn get_config() -> OffdictConfig {
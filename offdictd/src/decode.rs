@@ -0,0 +1,138 @@
+use std::fmt;
+use std::str::Utf8Error;
+
+use crate::def_bin::{DBKey, Def};
+
+/// Why a record could not be turned back into a [Def]
+#[derive(Debug)]
+pub enum DecodeFailure {
+    /// Key shorter than its length prefix claims
+    MalformedKey,
+    /// Headword is not valid UTF-8
+    Word(Utf8Error),
+    /// Dictionary name is not valid UTF-8
+    DictName(Utf8Error),
+    /// The value failed to deserialize
+    Value(bincode::Error),
+}
+
+impl fmt::Display for DecodeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MalformedKey => f.write_str("malformed key"),
+            Self::Word(e) => write!(f, "headword is not utf-8, {}", e),
+            Self::DictName(e) => write!(f, "dictionary name is not utf-8, {}", e),
+            Self::Value(e) => write!(f, "bad value, {}", e),
+        }
+    }
+}
+
+/// A record that failed to decode. Kept around so one damaged dictionary doesn't take the rest down.
+#[derive(Debug)]
+pub struct DecodeError {
+    pub key: Vec<u8>,
+    /// Best effort, lossy if the name itself is damaged
+    pub dict: Option<String>,
+    pub reason: DecodeFailure,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} in {}: {}",
+            String::from_utf8_lossy(&self.key),
+            self.dict.as_deref().unwrap_or("<unknown>"),
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Results of a scan, with whatever failed along the way.
+#[derive(Debug)]
+pub struct Decoded<T> {
+    pub value: T,
+    pub errors: Vec<DecodeError>,
+}
+
+impl<T> Decoded<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            errors: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Record {
+    pub word: String,
+    pub dict: String,
+    pub def: Def,
+}
+
+/// Splits and validates the key only. Used where the value is not needed.
+pub fn decode_key(k: &[u8]) -> Result<(String, String), DecodeError> {
+    let err = |dict: Option<String>, reason| DecodeError {
+        key: k.to_vec(),
+        dict,
+        reason,
+    };
+    let (word, dict) = DBKey::try_slice(k).ok_or_else(|| err(None, DecodeFailure::MalformedKey))?;
+    let dict = std::str::from_utf8(dict)
+        .map_err(|e| {
+            err(
+                Some(String::from_utf8_lossy(dict).into_owned()),
+                DecodeFailure::DictName(e),
+            )
+        })?
+        .to_owned();
+    let word = std::str::from_utf8(word)
+        .map_err(|e| err(Some(dict.clone()), DecodeFailure::Word(e)))?
+        .to_owned();
+    Ok((word, dict))
+}
+
+pub fn decode_record(k: &[u8], v: &[u8]) -> Result<Record, DecodeError> {
+    let (word, dict) = decode_key(k)?;
+    match bincode::deserialize::<Def>(v) {
+        Ok(def) => Ok(Record { word, dict, def }),
+        Err(e) => Err(DecodeError {
+            key: k.to_vec(),
+            dict: Some(dict),
+            reason: DecodeFailure::Value(e),
+        }),
+    }
+}
+
+#[test]
+fn test_decode_record() {
+    let def = Def {
+        word: Some("left".to_owned()),
+        dictName: Some("wikipedia".to_owned()),
+        ..Default::default()
+    };
+    let k = def.key();
+    let v = bincode::serialize(&def).unwrap();
+    let rec = decode_record(&k, &v).unwrap();
+    assert_eq!(rec.word, "left");
+    assert_eq!(rec.dict, "wikipedia");
+    assert_eq!(rec.def, def);
+
+    let bad = decode_record(&k, &v[..v.len() / 2]).unwrap_err();
+    assert_eq!(bad.dict.as_deref(), Some("wikipedia"));
+    assert!(matches!(bad.reason, DecodeFailure::Value(_)));
+
+    let mut k2 = DBKey::from("left", "");
+    k2.push(0xff);
+    assert!(matches!(
+        decode_record(&k2, &v).unwrap_err().reason,
+        DecodeFailure::DictName(_)
+    ));
+    assert!(matches!(
+        decode_record(&[0, 0, 0, 9, b'a'], &v).unwrap_err().reason,
+        DecodeFailure::MalformedKey
+    ));
+}
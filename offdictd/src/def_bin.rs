@@ -18,6 +18,7 @@ pub use std::fs::File;
 pub use std::io::{Read, Write};
 
 use crate::def;
+use crate::explain::Match;
 
 pub mod DBKey {
     pub fn slice(b: &[u8]) -> (&[u8], &[u8]) {
@@ -25,6 +26,12 @@ pub mod DBKey {
         let len: u32 = u32::from_be_bytes(c.try_into().unwrap());
         (&b[4..(4 + len as usize)], &b[(4 + len as usize)..])
     }
+    /// [slice] for keys that may be damaged
+    pub fn try_slice(b: &[u8]) -> Option<(&[u8], &[u8])> {
+        let len = u32::from_be_bytes(b.get(0..4)?.try_into().ok()?) as usize;
+        let end = 4usize.checked_add(len)?;
+        Some((b.get(4..end)?, b.get(end..)?))
+    }
     pub fn from(word: &str, dict: &str) -> Vec<u8> {
         let mut v: Vec<u8> = vec![];
         v.extend((word.len() as u32).to_be_bytes() as [u8; 4]);
@@ -134,6 +141,21 @@ impl<O: MaybeStringObject + 'static> IntoIterator for MaybeStructuredText<O> {
 
 pub type Example = MaybeStructuredText<ExampleInner>;
 
+impl MaybeStructuredText<ExampleInner> {
+    /// Each sentence with its translation, when there is one
+    pub fn pairs(&self) -> Vec<(String, Option<String>)> {
+        match self {
+            Self::Object(ExampleInner { EN: Some(en), CN }) => vec![(en.clone(), CN.clone())],
+            Self::Object(ExampleInner { EN: None, CN }) => {
+                CN.iter().map(|c| (c.clone(), None)).collect()
+            }
+            Self::Vec(v) => v.iter().flatten().map(|s| (s.clone(), None)).collect(),
+            Self::Str(s) => vec![(s.clone(), None)],
+            Self::None => vec![],
+        }
+    }
+}
+
 pub type Pronunciation = MaybeStructuredText<String>;
 
 #[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -167,6 +189,12 @@ impl From<Def> for super::SrcDef {
 pub struct WrapperDef {
     pub items: BTreeMap<String, Def>, // dictname to def
     pub word: String,
+    /// How the query led here when it wasn't matched as typed, like "running → run"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Why search returned it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matched: Option<Match>,
 }
 
 impl From<Def> for WrapperDef {
@@ -174,6 +202,8 @@ impl From<Def> for WrapperDef {
         Self {
             word: value.word.clone().unwrap(),
             items: BTreeMap::from_iter([(value.dictName.clone().unwrap(), value)]),
+            label: None,
+            matched: None,
         }
     }
 }
@@ -0,0 +1,258 @@
+//! Concordance over example sentences of every dictionary, for collocations without an entry
+
+use std::collections::HashSet;
+
+use rocksdb::{ColumnFamily, IteratorMode, WriteBatch};
+
+use crate::def_bin::{Def, ExampleInner, MaybeStructuredText};
+use crate::fulltext::tokenize;
+use crate::*;
+
+/// Column family with each sentence under `SENTENCE word \0 dict \0 n`,
+/// and postings `term \0 word \0 dict \0 n`
+pub const EXAMPLES_CF: &str = "examples";
+const SEP: u8 = 0;
+/// Terms are UTF-8, so never start with this byte
+const SENTENCE: u8 = 0xfe;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExampleHit {
+    pub sentence: String,
+    pub translation: Option<String>,
+    /// Entry the example comes from
+    pub word: String,
+    pub dict: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Sentence {
+    sentence: String,
+    translation: Option<String>,
+}
+
+/// Dictionaries write `~` for the headword
+pub fn expand_tilde(text: &str, word: &str) -> String {
+    text.replace('~', word)
+}
+
+fn all_examples(def: &Def, into: &mut Vec<(String, Option<String>)>) {
+    for ex in def.examples.iter().flatten() {
+        into.extend(ex.pairs());
+    }
+    for d in def.definitions.iter().chain(def.groups.iter()).flatten() {
+        all_examples(d, into);
+    }
+}
+
+fn doc_key(word: &str, dict: &str, n: u32) -> Vec<u8> {
+    let mut k = Vec::with_capacity(word.len() + dict.len() + 6);
+    k.extend(word.as_bytes());
+    k.push(SEP);
+    k.extend(dict.as_bytes());
+    k.push(SEP);
+    k.extend(n.to_be_bytes());
+    k
+}
+
+pub fn put_examples(batch: &mut WriteBatch, cf: &ColumnFamily, word: &str, dict: &str, def: &Def) {
+    let mut examples = vec![];
+    all_examples(def, &mut examples);
+    for (n, (sentence, translation)) in examples.into_iter().enumerate() {
+        let doc = doc_key(word, dict, n as u32);
+        let sentence = Sentence {
+            sentence: expand_tilde(&sentence, word),
+            translation: translation.map(|t| expand_tilde(&t, word)),
+        };
+        let terms: HashSet<String> = tokenize(&sentence.sentence)
+            .into_iter()
+            .chain(sentence.translation.iter().flat_map(|t| tokenize(t)))
+            .map(|t| t.term)
+            .collect();
+        for term in terms {
+            let mut k = term.into_bytes();
+            k.push(SEP);
+            k.extend(&doc);
+            batch.put_cf(cf, k, b"");
+        }
+        let mut k = vec![SENTENCE];
+        k.extend(&doc);
+        batch.put_cf(cf, k, bincode::serialize(&sentence).unwrap());
+    }
+}
+
+/// Word and dict of a document key
+fn split_doc(doc: &[u8]) -> Option<(&str, &str)> {
+    let w = doc.iter().position(|b| *b == SEP)?;
+    let rest = &doc[w + 1..];
+    let d = rest.iter().position(|b| *b == SEP)?;
+    Some((
+        std::str::from_utf8(&doc[..w]).ok()?,
+        std::str::from_utf8(&rest[..d]).ok()?,
+    ))
+}
+
+/// Postings of the term that has the fewest, read in step with the others until one list ends
+fn rarest_docs(db: &rocks, cf: &ColumnFamily, terms: &[&str]) -> Result<(usize, Vec<Vec<u8>>)> {
+    let prefixes: Vec<Vec<u8>> = terms
+        .iter()
+        .map(|t| {
+            let mut p = t.as_bytes().to_vec();
+            p.push(SEP);
+            p
+        })
+        .collect();
+    let mut iters: Vec<_> = prefixes
+        .iter()
+        .map(|p| db.iterator_cf(cf, IteratorMode::From(p, rocksdb::Direction::Forward)))
+        .collect();
+    let mut found = vec![vec![]; terms.len()];
+    loop {
+        for (i, it) in iters.iter_mut().enumerate() {
+            match it.next().transpose()? {
+                Some((k, _)) if k.starts_with(&prefixes[i]) => {
+                    found[i].push(k[prefixes[i].len()..].to_vec())
+                }
+                _ => return Ok((i, std::mem::take(&mut found[i]))),
+            }
+        }
+    }
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// Sentences containing the word or phrase, in the sentence or its translation. Shorter ones first.
+    pub fn examples(&self, query: &str, opts: &SearchOptions) -> Result<Partial<Vec<ExampleHit>>> {
+        let opts = &opts.started();
+        let terms: HashSet<String> = tokenize(query).into_iter().map(|t| t.term).collect();
+        let phrase = query.trim().to_lowercase();
+        if phrase.is_empty() {
+            return Ok(Partial::default());
+        }
+        let mut partial = false;
+        let db = self.db.read().unwrap();
+        let cf = cf_handle(&db, EXAMPLES_CF)?;
+
+        let mut docs: Vec<(Vec<u8>, Option<Vec<u8>>)> = vec![];
+        if terms.is_empty() {
+            // Only stopwords, which have no postings, so every sentence is read
+            for r in db.iterator_cf(
+                cf,
+                IteratorMode::From(&[SENTENCE], rocksdb::Direction::Forward),
+            ) {
+                let (k, v) = r?;
+                if k.first() != Some(&SENTENCE) {
+                    break;
+                }
+                if opts.budget.exhausted() {
+                    partial = true;
+                    break;
+                }
+                let doc = &k[1..];
+                if split_doc(doc).is_some_and(|(_, dict)| opts.keeps_dict(dict)) {
+                    docs.push((doc.to_vec(), Some(v.to_vec())));
+                }
+            }
+        } else {
+            let terms: Vec<&str> = terms.iter().map(String::as_str).collect();
+            let (rarest, candidates) = rarest_docs(&db, cf, &terms)?;
+            'doc: for doc in candidates {
+                if opts.budget.exhausted() {
+                    partial = true;
+                    break;
+                }
+                if !split_doc(&doc).is_some_and(|(_, dict)| opts.keeps_dict(dict)) {
+                    continue;
+                }
+                // Every term has to be there
+                for (i, term) in terms.iter().enumerate() {
+                    if i == rarest {
+                        continue;
+                    }
+                    let mut k = term.as_bytes().to_vec();
+                    k.push(SEP);
+                    k.extend(&doc);
+                    if db.get_cf(cf, &k)?.is_none() {
+                        continue 'doc;
+                    }
+                }
+                docs.push((doc, None));
+            }
+        }
+
+        let mut hits = vec![];
+        for (doc, v) in docs {
+            if opts.budget.exhausted() {
+                partial = true;
+                break;
+            }
+            let Some((word, dict)) = split_doc(&doc) else {
+                continue;
+            };
+            let v = match v {
+                Some(v) => v,
+                None => {
+                    let mut k = vec![SENTENCE];
+                    k.extend(&doc);
+                    let Some(v) = db.get_cf(cf, &k)? else {
+                        continue;
+                    };
+                    v
+                }
+            };
+            let Ok(s) = bincode::deserialize::<Sentence>(&v) else {
+                continue;
+            };
+            // Terms may be apart, the phrase has to be there as typed
+            let contains = |t: &str| t.to_lowercase().contains(&phrase);
+            if contains(&s.sentence) || s.translation.as_deref().is_some_and(contains) {
+                hits.push(ExampleHit {
+                    sentence: s.sentence,
+                    translation: s.translation,
+                    word: word.to_owned(),
+                    dict: dict.to_owned(),
+                });
+            }
+        }
+        hits.sort_by(|a, b| {
+            (a.sentence.len(), &a.sentence, &a.word, &a.dict).cmp(&(
+                b.sentence.len(),
+                &b.sentence,
+                &b.word,
+                &b.dict,
+            ))
+        });
+        hits.dedup_by(|a, b| a.sentence == b.sentence);
+        hits.truncate(opts.num);
+        Ok(Partial {
+            value: hits,
+            partial,
+        })
+    }
+}
+
+#[test]
+fn test_examples() {
+    assert_eq!(expand_tilde("~ out of time", "run"), "run out of time");
+
+    let def = Def {
+        examples: Some(vec![MaybeStructuredText::Object(ExampleInner {
+            EN: Some("Time ~s out.".to_owned()),
+            CN: Some("时间用完了。".to_owned()),
+        })]),
+        definitions: Some(vec![Def {
+            examples: Some(vec![MaybeStructuredText::Str("~ away".to_owned())]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let mut found = vec![];
+    all_examples(&def, &mut found);
+    assert_eq!(
+        found,
+        vec![
+            ("Time ~s out.".to_owned(), Some("时间用完了。".to_owned())),
+            ("~ away".to_owned(), None)
+        ]
+    );
+    let doc = doc_key("run", "dict", 3);
+    assert_eq!(split_doc(&doc), Some(("run", "dict")));
+}
@@ -0,0 +1,168 @@
+//! Why a result came back, attached to each one [crate::Diverge::search] gives.
+//! The kinds follow the stages of search, and of the fst index's `Flags` for spelling matches.
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use crate::normalize::normalize;
+use crate::options::{edit_distance, MatchMode};
+use crate::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    /// As typed
+    Exact,
+    /// Differs only in case
+    CaseInsensitive,
+    /// Same once accents, width, spaces and punctuation are folded
+    Normalized,
+    /// The query is an inflected form, "running" for "run"
+    Inflection,
+    /// Sounds like the query
+    Phonetic,
+    /// Starts with the query
+    Prefix,
+    /// Within some edits of the query
+    Fuzzy,
+}
+
+/// Lower first. Compared bitwise so results stay hashable and ordered.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(transparent)]
+pub struct Score(pub f32);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for Score {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Match {
+    pub kind: MatchKind,
+    /// Edits between the query and the headword, or the part of it matched in prefix mode.
+    /// 0 for inflections and phonetic matches, which aren't found by spelling.
+    pub distance: usize,
+    /// Chars of the headword the query matched, `[start, end)`
+    pub span: (usize, usize),
+    /// What found it: `normalize`, `lemma`, `phonetic`, or the index, `strprox` or `fst`
+    pub source: String,
+    /// Edit distance, plus the frequency penalty times `frequency_weight` for index candidates
+    /// once a list was imported. Comparable within a kind.
+    pub score: Score,
+}
+
+impl Match {
+    /// Fuzzy and phonetic matches, which the panel dims
+    pub fn is_approximate(&self) -> bool {
+        matches!(self.kind, MatchKind::Fuzzy | MatchKind::Phonetic)
+    }
+}
+
+impl std::fmt::Display for Match {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = serde_yaml::to_string(&self.kind).unwrap_or_default();
+        f.write_fmt(format_args!("{} via {}", kind.trim(), self.source))?;
+        if self.distance > 0 {
+            f.write_fmt(format_args!(", {} edits", self.distance))?;
+        }
+        Ok(())
+    }
+}
+
+/// Edits to the closest prefix of `cand`, and that prefix's length in chars
+pub fn closest_prefix(query: &str, cand: &str) -> (usize, usize) {
+    let c: Vec<char> = cand.chars().collect();
+    let q = query.chars().count();
+    (q.saturating_sub(1)..=q + 1)
+        .filter(|n| *n <= c.len())
+        .map(|n| (edit_distance(query, &c[..n].iter().collect::<String>()), n))
+        .min()
+        .unwrap_or_else(|| (edit_distance(query, cand), c.len()))
+}
+
+/// How a headword found by its spelling relates to the query: kind, edits and the chars matched
+pub fn spelling_match(
+    query: &str,
+    word: &str,
+    mode: MatchMode,
+) -> (MatchKind, usize, (usize, usize)) {
+    let len = word.chars().count();
+    let (q, w) = (query.to_lowercase(), word.to_lowercase());
+    if query == word {
+        (MatchKind::Exact, 0, (0, len))
+    } else if q == w {
+        (MatchKind::CaseInsensitive, 0, (0, len))
+    } else if normalize(query) == normalize(word) {
+        (MatchKind::Normalized, 0, (0, len))
+    } else if mode == MatchMode::Prefix && w.starts_with(&q) {
+        (MatchKind::Prefix, 0, (0, q.chars().count()))
+    } else if mode == MatchMode::Prefix {
+        let (d, n) = closest_prefix(&q, &w);
+        (MatchKind::Fuzzy, d, (0, n))
+    } else {
+        (MatchKind::Fuzzy, edit_distance(&q, &w), (0, len))
+    }
+}
+
+/// For headwords found by something else than spelling, scored by how far they are spelled
+pub fn other_match(query: &str, word: &str, kind: MatchKind, source: &str) -> Match {
+    let spelled = edit_distance(&query.to_lowercase(), &word.to_lowercase());
+    Match {
+        kind,
+        // Edits only explain spelling matches, this one matched for another reason
+        distance: 0,
+        span: (0, word.chars().count()),
+        source: source.to_owned(),
+        score: Score(spelled as f32),
+    }
+}
+
+#[test]
+fn test_explain() {
+    use MatchKind::*;
+    let m = |q, w, mode| spelling_match(q, w, mode);
+    assert_eq!(m("run", "run", MatchMode::Prefix), (Exact, 0, (0, 3)));
+    assert_eq!(
+        m("Run", "run", MatchMode::Prefix),
+        (CaseInsensitive, 0, (0, 3))
+    );
+    assert_eq!(m("cafe", "café", MatchMode::Word).0, Normalized);
+    assert_eq!(
+        m("enviro", "environment", MatchMode::Prefix),
+        (Prefix, 0, (0, 6))
+    );
+    assert_eq!(m("enviro", "environment", MatchMode::Word).0, Fuzzy);
+    assert_eq!(
+        m("envro", "environment", MatchMode::Prefix),
+        (Fuzzy, 1, (0, 6))
+    );
+    assert_eq!(m("teh", "the", MatchMode::Word), (Fuzzy, 2, (0, 3)));
+    assert_eq!(closest_prefix("ab", "x"), (2, 1));
+
+    assert_eq!(
+        other_match("nolij", "knowledge", Phonetic, "phonetic").to_string(),
+        "phonetic via phonetic"
+    );
+}
@@ -0,0 +1,193 @@
+//! Word frequency ranks, so "teh" finds "the" before rare headwords at the same distance.
+//! Imported from a `word<TAB>count` list and kept next to the index.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader};
+
+use crate::explain::closest_prefix;
+use crate::normalize::normalize;
+use crate::options::{edit_distance, MatchMode};
+use crate::*;
+
+pub const FREQ_FILE: &str = "freq";
+/// Completions given when the request doesn't say
+pub const DEFAULT_COMPLETIONS: usize = 10;
+/// Headwords the frequency list doesn't have, read for one completion at most, the first in key order
+pub const COMPLETE_SCAN: usize = 5000;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Frequencies {
+    /// Normalized word to rank, 0 being the most frequent. Sorted, for [Self::with_prefix].
+    ranks: BTreeMap<String, u32>,
+}
+
+impl Frequencies {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(bincode::deserialize(&fs::read(path)?)?)
+    }
+
+    /// Reads `word<TAB>count` lines in any order. Lines that don't parse are skipped.
+    pub fn from_tsv(list: impl BufRead) -> Result<Self> {
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for line in list.lines() {
+            let line = line?;
+            let Some((word, count)) = line.split_once('\t') else {
+                continue;
+            };
+            let (word, Ok(count)) = (normalize(word.trim()), count.trim().parse::<u64>()) else {
+                continue;
+            };
+            if !word.is_empty() {
+                // Spellings that normalize the same add up
+                *counts.entry(word).or_default() += count;
+            }
+        }
+        let mut sorted: Vec<(String, u64)> = counts.into_iter().collect();
+        sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(Self {
+            ranks: sorted
+                .into_iter()
+                .enumerate()
+                .map(|(rank, (w, _))| (w, rank as u32))
+                .collect(),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, bincode::serialize(self)?)?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranks.len()
+    }
+
+    pub fn rank(&self, word: &str) -> Option<u32> {
+        self.ranks.get(&normalize(word)).copied()
+    }
+
+    /// Normalized words of the list starting with the prefix, most frequent first
+    pub fn with_prefix(&self, prefix: &str) -> Vec<&str> {
+        let prefix = normalize(prefix);
+        let mut words: Vec<(&str, u32)> = self
+            .ranks
+            .range(prefix.clone()..)
+            .take_while(|(w, _)| w.starts_with(&prefix))
+            .map(|(w, r)| (w.as_str(), *r))
+            .collect();
+        words.sort_by_key(|w| w.1);
+        words.into_iter().map(|w| w.0).collect()
+    }
+
+    /// From 0 for the most frequent word to 1 for words not in the list, on a log scale
+    pub fn penalty(&self, word: &str) -> f32 {
+        match self.rank(word) {
+            Some(r) => ((r + 1) as f32).ln() / ((self.ranks.len() + 1) as f32).ln(),
+            None => 1.,
+        }
+    }
+
+    /// Orders by edit distance plus `weight` times [Self::penalty], frequency breaking ties.
+    /// At weight 1 a common word can overtake a rare one by up to one edit.
+    pub fn rerank(
+        &self,
+        query: &str,
+        cands: candidates,
+        mode: MatchMode,
+        weight: f32,
+    ) -> candidates {
+        let q = query.to_lowercase();
+        let mut scored: Vec<(f32, f32, String)> = cands
+            .into_iter()
+            .map(|c| {
+                let penalty = self.penalty(&c);
+                let d = distance(&q, &c.to_lowercase(), mode) as f32;
+                (d + weight * penalty, penalty, c)
+            })
+            .collect();
+        scored.sort_by(|a, b| {
+            a.0.partial_cmp(&b.0)
+                .unwrap_or(Equal)
+                .then(a.1.partial_cmp(&b.1).unwrap_or(Equal))
+        });
+        scored.into_iter().map(|s| s.2).collect()
+    }
+}
+
+/// Common words first, then shorter ones, the first `num` of them
+pub fn rank_completions(
+    mut words: candidates,
+    freq: Option<&Frequencies>,
+    num: usize,
+) -> candidates {
+    // Penalties aren't negative, so their bits order like them
+    words.sort_by_cached_key(|w| {
+        let penalty = freq.map_or(1., |f| f.penalty(w));
+        (penalty.to_bits(), w.chars().count())
+    });
+    words.truncate(num);
+    words
+}
+
+/// Edit distance to the whole headword, or to its closest prefix in [MatchMode::Prefix]
+fn distance(query: &str, cand: &str, mode: MatchMode) -> usize {
+    match mode {
+        MatchMode::Word => edit_distance(query, cand),
+        MatchMode::Prefix => closest_prefix(query, cand).0,
+    }
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// [Frequencies::penalty] by the index's list, 1 for every word without one
+    pub fn freq_penalty(&self, word: &str) -> f32 {
+        self.set
+            .as_ref()
+            .and_then(|ix| ix.frequencies())
+            .map_or(1., |f| f.penalty(word))
+    }
+
+    /// Replaces the frequency list and reloads the index to use it
+    pub fn import_frequencies(&mut self, list: &Path) -> Result<usize> {
+        let freq = Frequencies::from_tsv(BufReader::new(File::open(list)?))?;
+        freq.save(&self.dirpath.join(FREQ_FILE))?;
+        let index = Ix::path(&self.dirpath);
+        if index.exists() {
+            self.set = Some(Ix::load_file(&index)?);
+            self.results.clear();
+        }
+        Ok(freq.len())
+    }
+}
+
+#[test]
+fn test_frequencies() {
+    let freq = Frequencies::from_tsv("the\t1000\nThe\t10\nteak\t5\nteh\tx\nten\t300\n".as_bytes())
+        .unwrap();
+    assert_eq!(freq.len(), 3);
+    assert_eq!(freq.rank("THE"), Some(0));
+    assert_eq!(freq.rank("ten"), Some(1));
+    assert_eq!(freq.penalty("the"), 0.);
+    assert_eq!(freq.penalty("tehran"), 1.);
+    assert_eq!(freq.with_prefix("TE"), vec!["ten", "teak"]);
+    assert!(freq.with_prefix("x").is_empty());
+
+    let cands = vec![
+        "teak".to_owned(),
+        "tehran".to_owned(),
+        "ten".to_owned(),
+        "the".to_owned(),
+    ];
+    assert_eq!(
+        freq.rerank("teh", cands.clone(), MatchMode::Word, 0.),
+        vec!["ten", "the", "teak", "tehran"]
+    );
+    assert_eq!(
+        freq.rerank("teh", cands.clone(), MatchMode::Prefix, 1.),
+        vec!["the", "tehran", "ten", "teak"]
+    );
+    assert_eq!(
+        rank_completions(cands.clone(), Some(&freq), 3),
+        vec!["the", "ten", "teak"]
+    );
+    assert_eq!(rank_completions(cands, None, 2), vec!["ten", "the"]);
+}
@@ -14,6 +14,7 @@ use fst::{IntoStreamer, Set, Streamer};
 
 use timed;
 
+use crate::options::MatchMode;
 use crate::*;
 use bitmask_enum::bitmask;
 
@@ -101,7 +102,7 @@ impl Into<f32> for &Metrics {
             if self.flags.contains(Flags::LevenFull) {
                 step1 -= 0.1;
             }
-            step1 + (self.short / self.num) as f32 * 0.1
+            step1 + self.short as f32 / self.num as f32 * 0.1
         }
     }
 }
@@ -109,94 +110,166 @@ impl Into<f32> for &Metrics {
 pub use fst::Set as fstset;
 pub type fstmmap = fstset<Mmap>;
 
+/// Fuzzy stages of the fst walk all states within the distance, which grows fast past 2
+const FST_MAX_DISTANCE: u32 = 2;
+
+/// Cap on keys taken from each stage
+const STAGE_CAP: i32 = 50;
+
+fn rank(map: BTreeMap<String, Metrics>) -> candidates {
+    let mut ve: Vec<(String, Metrics)> = map.into_iter().collect();
+    let le = ve.len() as u32;
+    ve.sort_unstable_by_key(|x| x.0.len());
+    for (a, b) in ve.iter_mut().enumerate() {
+        b.1.short = a as u32;
+        b.1.num = le;
+    }
+    ve.sort_by(|a, b| {
+        let sa: f32 = a.1.borrow().into();
+        let sb: f32 = b.1.borrow().into();
+        sa.partial_cmp(&sb).unwrap_or(Ordering::Equal)
+    });
+    ve.into_iter().map(|x| x.0).collect()
+}
+
+/// Exact and case insensitive hits, plus prefix hits in [MatchMode::Prefix]
+fn exact_stages<D: AsRef<[u8]>>(
+    set: &fstset<D>,
+    q: &str,
+    opts: &SearchOptions,
+    map: &mut BTreeMap<String, Metrics>,
+) -> Result<()> {
+    let low = q.to_lowercase();
+    match opts.mode {
+        MatchMode::Prefix => {
+            let star = automaton::Str::new(q).starts_with(); // matches strings starting with q
+            let stream = set.search(&star).into_stream();
+            collect_to_map(stream, map, Flags::ExactPrefix, q, STAGE_CAP)?;
+            if low != q {
+                let star = automaton::Str::new(&low).starts_with();
+                let stream = set.search(&star).into_stream();
+                collect_to_map(stream, map, Flags::ExactPrefixCaseI, q, STAGE_CAP)?;
+            }
+        }
+        MatchMode::Word => {
+            for w in [q, low.as_str()] {
+                let stream = set.search(automaton::Str::new(w)).into_stream();
+                collect_to_map(stream, map, Flags::none(), q, STAGE_CAP)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Only the stages the fst is good at, for [crate::index::IndexKind::Hybrid]
+pub fn exact_and_prefix<D: AsRef<[u8]>>(
+    set: &fstset<D>,
+    q: &str,
+    opts: &SearchOptions,
+) -> Result<candidates> {
+    let mut map = BTreeMap::new();
+    exact_stages(set, q, opts, &mut map)?;
+    let mut res = rank(map);
+    res.truncate(opts.num);
+    Ok(res)
+}
+
 impl Indexer for fstmmap {
     const FILE_NAME: &'static str = "fst";
-    type Param = bool;
+    type Param = SearchOptions;
     fn load_file(pp: &Path) -> Result<Self> {
         println!("loading FST index");
-        let mmap = unsafe { Mmap::map(&File::open(pp).unwrap()).unwrap() };
-        let set = fst::Set::new(mmap).unwrap();
+        let mmap = unsafe { Mmap::map(&File::open(pp)?)? };
+        let set = fst::Set::new(mmap)?;
         Ok(set)
     }
     #[timed]
-    fn query(&self, q: &str, expensive: bool) -> Result<candidates> {
+    fn query(&self, q: &str, opts: &SearchOptions) -> Result<candidates> {
         let set = self;
         let len = q.chars().count();
+        let distance = (opts.distance as u32).min(FST_MAX_DISTANCE);
         let mut map: BTreeMap<String, Metrics> = BTreeMap::new();
-        let keys = if len <= 2 {
-            let star = automaton::Str::new(q).starts_with(); // matches strings starting with q
-            let stream = set.search(&star).into_stream();
-
-            collect_to_map(stream, &mut map, Flags::ExactPrefix, q, 50)?;
-        } else {
-            let star = automaton::Str::new(q).starts_with(); // matches strings starting with q
-            let stream = set.search(&star).into_stream();
-            collect_to_map(stream, &mut map, Flags::ExactPrefix, q, 50)?;
-            // Capping search results here leads to suboptimal results
-
-            let low = q.to_lowercase();
-            let star = automaton::Str::new(&low).starts_with();
-            let stream = set.search(&star).into_stream();
-            collect_to_map(stream, &mut map, Flags::ExactCaseIns, q, 50)?;
+        exact_stages(set, q, opts, &mut map)?;
 
+        // Short queries have too many neighbours for fuzzy stages to help
+        if len > 2 && distance >= 1 && !opts.budget.exhausted() {
             let lev = Levenshtein::new(q, 1)?;
-            let pre = lev.starts_with(); // also matches strings starting with q when d=0
-
-            let stream = set.search(&pre).into_stream();
-            collect_to_map(stream, &mut map, Flags::Leven1, q, 50)?;
-        };
+            match opts.mode {
+                MatchMode::Prefix => {
+                    let pre = lev.starts_with(); // also matches strings starting with q when d=0
+                    let stream = set.search(&pre).into_stream();
+                    collect_to_map(stream, &mut map, Flags::Leven1, q, STAGE_CAP)?;
+                }
+                MatchMode::Word => {
+                    let stream = set.search(&lev).into_stream();
+                    collect_to_map(stream, &mut map, Flags::Leven1, q, STAGE_CAP)?;
+                }
+            }
+        }
 
         // Fallbacks, levenshtein of distance 2
-        if expensive {
+        if len > 2 && distance >= 2 && !opts.budget.exhausted() {
             if map.is_empty() {
                 let lev = Levenshtein::new(q, 2)?;
-                let pre = lev.starts_with();
-
-                let stream = set.search(&pre).into_stream();
-                collect_to_map(stream, &mut map, Flags::Leven2, q, 50)?;
+                match opts.mode {
+                    MatchMode::Prefix => {
+                        let pre = lev.starts_with();
+                        let stream = set.search(&pre).into_stream();
+                        collect_to_map(stream, &mut map, Flags::Leven2, q, STAGE_CAP)?;
+                    }
+                    MatchMode::Word => {
+                        let stream = set.search(&lev).into_stream();
+                        collect_to_map(stream, &mut map, Flags::Leven2, q, STAGE_CAP)?;
+                    }
+                }
             }
             let lev = Levenshtein::new(q, 1)?;
             let stream = set.search(&lev).into_stream();
-            collect_to_map(stream, &mut map, Flags::LevenFull, q, 50)?;
+            collect_to_map(stream, &mut map, Flags::LevenFull, q, STAGE_CAP)?;
         }
 
-        let mut ve: Vec<(String, Metrics)> = map.into_iter().collect();
-        let le = ve.len() as u32;
-        ve.sort_unstable_by_key(|x| x.0.len());
-        for (a, b) in ve.iter_mut().enumerate() {
-            b.1.short = a as u32;
-            b.1.num = le;
-        }
-        ve.sort_by(|a, b| {
-            let sa: f32 = a.1.borrow().into();
-            let sb: f32 = b.1.borrow().into();
-            sa.partial_cmp(&sb).unwrap_or(Ordering::Equal)
-        });
-        Ok(ve.into_iter().map(|x| x.0).collect())
+        let mut res = rank(map);
+        res.truncate(opts.num);
+        Ok(res)
     }
+    /// Words must come sorted
     fn build_all(sorted: impl IntoIterator<Item = String>, px: &Path) -> Result<()> {
-        let mut w = BufWriter::new(File::create(px).unwrap());
-        let mut bu = SetBuilder::new(&mut w).unwrap();
-        sorted.into_iter().for_each(|k| {
-            bu.insert(k).unwrap();
-        });
+        let mut w = BufWriter::new(File::create(px)?);
+        let mut bu = SetBuilder::new(&mut w)?;
+        for k in sorted {
+            bu.insert(k)?;
+        }
         bu.finish()?;
         Ok(())
     }
     fn count(&self) -> usize {
         self.len()
     }
-}
-
-impl Diverge for offdict<fstmmap> {
-    type Ix = fstmmap;
-    fn search(&self, query: &str, num: usize, expensive: bool) -> Result<Vec<DefItemWrapped>> {
-        let mut cands = self.candidates(query, expensive)?;
-        cands.truncate(num);
-        let mut res: Vec<DefItemWrapped> = vec![];
-        for s in cands {
-            res.push(self.retrieve(s).unwrap());
+    fn for_each_word(&self, f: &mut dyn FnMut(&str) -> bool) {
+        let mut stream = self.stream();
+        while let Some(k) = stream.next() {
+            match std::str::from_utf8(k) {
+                Ok(w) if !f(w) => return,
+                _ => (),
+            }
         }
-        Ok(res)
     }
 }
+
+#[test]
+fn test_prefix_rank() {
+    let set = fstset::from_iter(["rubric", "run", "running", "runs"]).unwrap();
+    let opts = SearchOptions {
+        mode: MatchMode::Prefix,
+        ..Default::default()
+    };
+    let res = exact_and_prefix(&set, "ru", &opts).unwrap();
+    assert_eq!(res, vec!["run", "runs", "rubric", "running"]);
+    let longer = Metrics {
+        short: 1,
+        flags: Flags::ExactPrefix,
+        num: 2,
+    };
+    let score: f32 = (&longer).into();
+    assert!(score > 0.2);
+}
@@ -0,0 +1,464 @@
+//! Reverse lookup, from a meaning to headwords. An inverted index over the text of every sense,
+//! ranked with BM25.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use rocksdb::{ColumnFamily, IteratorMode, WriteBatch};
+
+use crate::def_bin::{Def, Pronunciation};
+use crate::examples::{put_examples, EXAMPLES_CF};
+use crate::*;
+
+/// Column family of postings, `term \0 word \0 dict \0 path`, the corpus stats and document frequencies
+pub const FULLTEXT_CF: &str = "fulltext";
+const SEP: u8 = 0;
+/// Terms are UTF-8, so never start with this byte
+const STATS_KEY: &[u8] = &[0xff];
+/// Before each term, for the senses it occurs in
+const DF_PREFIX: u8 = 0xfe;
+
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+/// Entries ranked per result asked for, the rest left for those the overlay removed
+const RANKED_PER_RESULT: usize = 2;
+
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "in", "into", "is", "it", "of",
+    "on", "or", "so", "sth", "sb", "that", "the", "to", "with",
+];
+
+#[derive(Debug, PartialEq)]
+pub struct Token {
+    pub term: String,
+    /// Byte offsets into the text
+    pub start: usize,
+    pub end: usize,
+}
+
+pub fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30ff // kana
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xac00..=0xd7af // hangul
+        | 0xf900..=0xfaff
+        | 0x20000..=0x2fa1f)
+}
+
+/// Lowercased words for alphabetic scripts, overlapping bigrams for CJK runs
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let mut res = vec![];
+    let mut word: Option<usize> = None;
+    let mut cjk: Vec<(usize, char)> = vec![];
+    let flush_cjk = |cjk: &mut Vec<(usize, char)>, res: &mut Vec<Token>| {
+        if cjk.len() == 1 {
+            let (i, c) = cjk[0];
+            res.push(Token {
+                term: c.to_string(),
+                start: i,
+                end: i + c.len_utf8(),
+            });
+        }
+        for w in cjk.windows(2) {
+            let ((i, a), (j, b)) = (w[0], w[1]);
+            res.push(Token {
+                term: [a, b].iter().collect(),
+                start: i,
+                end: j + b.len_utf8(),
+            });
+        }
+        cjk.clear();
+    };
+    let flush_word = |start: usize, end: usize, res: &mut Vec<Token>| {
+        let term = text[start..end].trim_matches('\'').to_lowercase();
+        if !term.is_empty() && !STOPWORDS.contains(&term.as_str()) {
+            res.push(Token { term, start, end });
+        }
+    };
+    for (i, c) in text.char_indices() {
+        if is_cjk(c) {
+            if let Some(s) = word.take() {
+                flush_word(s, i, &mut res);
+            }
+            cjk.push((i, c));
+        } else if c.is_alphanumeric() || (c == '\'' && word.is_some()) {
+            flush_cjk(&mut cjk, &mut res);
+            word.get_or_insert(i);
+        } else {
+            flush_cjk(&mut cjk, &mut res);
+            if let Some(s) = word.take() {
+                flush_word(s, i, &mut res);
+            }
+        }
+    }
+    flush_cjk(&mut cjk, &mut res);
+    if let Some(s) = word {
+        flush_word(s, text.len(), &mut res);
+    }
+    res
+}
+
+/// The searchable text of one sense, without its subsenses
+pub fn sense_text(def: &Def) -> String {
+    let mut parts: Vec<String> = [&def.EN, &def.CN, &def.info]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    for ex in def.examples.iter().flatten() {
+        for (s, tr) in ex.pairs() {
+            parts.push(s);
+            parts.extend(tr);
+        }
+    }
+    parts.join("\n")
+}
+
+fn subsenses(def: &Def) -> impl Iterator<Item = &Def> {
+    def.definitions.iter().chain(def.groups.iter()).flatten()
+}
+
+/// Pronunciation fields of an entry and of its senses, as the dictionary wrote them
+pub fn pronunciations(def: &Def) -> Vec<String> {
+    let mut res = vec![];
+    match &def.pronunciation {
+        Some(Pronunciation::Str(s)) | Some(Pronunciation::Object(s)) => res.push(s.clone()),
+        Some(Pronunciation::Vec(v)) => res.extend(v.iter().flatten().cloned()),
+        _ => (),
+    }
+    for d in subsenses(def) {
+        res.extend(pronunciations(d));
+    }
+    res
+}
+
+/// Follows indices through [Def::definitions]
+pub fn sense_at<'d>(def: &'d Def, path: &[u16]) -> Option<&'d Def> {
+    let mut d = def;
+    for i in path {
+        d = subsenses(d).nth(*i as usize)?;
+    }
+    Some(d)
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct FullTextStats {
+    pub docs: u64,
+    /// Tokens over all senses
+    pub len: u64,
+    /// Senses each term occurs in, stored under [DF_PREFIX] keys
+    #[serde(skip)]
+    pub df: HashMap<String, u64>,
+}
+
+impl std::ops::AddAssign for FullTextStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.docs += rhs.docs;
+        self.len += rhs.len;
+        for (term, n) in rhs.df {
+            *self.df.entry(term).or_default() += n;
+        }
+    }
+}
+
+fn df_key(term: &str) -> Vec<u8> {
+    let mut k = vec![DF_PREFIX];
+    k.extend(term.as_bytes());
+    k
+}
+
+fn read_df(db: &rocks, cf: &ColumnFamily, term: &str) -> Result<Option<u64>> {
+    Ok(match db.get_cf(cf, df_key(term))? {
+        Some(v) => Some(bincode::deserialize(&v)?),
+        None => None,
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Posting {
+    tf: u32,
+    /// Tokens in the sense
+    len: u32,
+}
+
+fn posting_key(term: &str, word: &str, dict: &str, path: &[u16]) -> Vec<u8> {
+    let mut k = Vec::with_capacity(term.len() + word.len() + dict.len() + 3 + path.len() * 2);
+    k.extend(term.as_bytes());
+    k.push(SEP);
+    k.extend(word.as_bytes());
+    k.push(SEP);
+    k.extend(dict.as_bytes());
+    k.push(SEP);
+    for i in path {
+        k.extend(i.to_be_bytes());
+    }
+    k
+}
+
+/// Word, dict and path after the term prefix
+fn split_doc(rest: &[u8]) -> Option<(&str, &str, Vec<u16>)> {
+    let w = rest.iter().position(|b| *b == SEP)?;
+    let d = w + 1 + rest[w + 1..].iter().position(|b| *b == SEP)?;
+    let path = rest[d + 1..]
+        .chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect();
+    Some((
+        std::str::from_utf8(&rest[..w]).ok()?,
+        std::str::from_utf8(&rest[w + 1..d]).ok()?,
+        path,
+    ))
+}
+
+fn put_sense(
+    batch: &mut WriteBatch,
+    cf: &ColumnFamily,
+    word: &str,
+    dict: &str,
+    def: &Def,
+    path: &mut Vec<u16>,
+    stats: &mut FullTextStats,
+) {
+    let tokens = tokenize(&sense_text(def));
+    if !tokens.is_empty() {
+        let mut tf: HashMap<&str, u32> = HashMap::new();
+        for t in &tokens {
+            *tf.entry(&t.term).or_default() += 1;
+        }
+        let len = tokens.len() as u32;
+        for (term, tf) in tf {
+            *stats.df.entry(term.to_owned()).or_default() += 1;
+            let v = bincode::serialize(&Posting { tf, len }).unwrap();
+            batch.put_cf(cf, posting_key(term, word, dict, path), v);
+        }
+        stats.docs += 1;
+        stats.len += len as u64;
+    }
+    for (i, d) in subsenses(def).enumerate() {
+        path.push(i as u16);
+        put_sense(batch, cf, word, dict, d, path, stats);
+        path.pop();
+    }
+}
+
+/// Postings for every sense of an entry
+pub fn put_senses(
+    batch: &mut WriteBatch,
+    cf: &ColumnFamily,
+    word: &str,
+    dict: &str,
+    def: &Def,
+    stats: &mut FullTextStats,
+) {
+    put_sense(batch, cf, word, dict, def, &mut vec![], stats);
+}
+
+fn read_stats(db: &rocks, cf: &ColumnFamily) -> Result<FullTextStats> {
+    Ok(match db.get_cf(cf, STATS_KEY)? {
+        Some(v) => bincode::deserialize(&v)?,
+        None => FullTextStats::default(),
+    })
+}
+
+/// Adds what an import indexed. Reimporting a dictionary counts it twice, `reverse --rebuild` recounts.
+pub fn add_stats(db: &rocks, cf: &ColumnFamily, add: FullTextStats) -> Result<()> {
+    let mut batch = WriteBatch::default();
+    for (term, n) in &add.df {
+        let df = read_df(db, cf, term)?.unwrap_or(0) + n;
+        batch.put_cf(cf, df_key(term), bincode::serialize(&df)?);
+    }
+    let mut stats = read_stats(db, cf)?;
+    stats += add;
+    batch.put_cf(cf, STATS_KEY, bincode::serialize(&stats)?);
+    db.write(batch)?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FullTextHit {
+    pub word: String,
+    pub dict: String,
+    /// Where the sense sits under [Def::definitions]
+    pub path: Vec<u16>,
+    pub score: f32,
+    /// Text of the matching sense
+    pub text: String,
+    /// Byte ranges of `text` that match the query
+    pub spans: Vec<(usize, usize)>,
+}
+
+/// Merged ranges of the tokens that are query terms
+pub fn highlight(text: &str, terms: &HashSet<String>) -> Vec<(usize, usize)> {
+    let mut spans: Vec<(usize, usize)> = vec![];
+    for t in tokenize(text) {
+        if !terms.contains(&t.term) {
+            continue;
+        }
+        match spans.last_mut() {
+            Some(last) if t.start <= last.1 => last.1 = last.1.max(t.end),
+            _ => spans.push((t.start, t.end)),
+        }
+    }
+    spans
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// Senses ranked by BM25, the best one per headword and dictionary
+    pub fn fulltext(&self, query: &str, opts: &SearchOptions) -> Result<Partial<Vec<FullTextHit>>> {
+        let opts = &opts.started();
+        let terms: HashSet<String> = tokenize(query).into_iter().map(|t| t.term).collect();
+        let mut partial = false;
+        let mut scores: HashMap<(String, String, Vec<u16>), f32> = HashMap::new();
+        {
+            let db = self.db.read().unwrap();
+            let cf = cf_handle(&db, FULLTEXT_CF)?;
+            let stats = read_stats(&db, cf)?;
+            let n = stats.docs.max(1) as f32;
+            let avgdl = stats.len as f32 / n;
+            for term in &terms {
+                let mut prefix = term.as_bytes().to_vec();
+                prefix.push(SEP);
+                let mut postings = vec![];
+                // Counted over every dictionary, the filter only picks which to score
+                let mut seen = 0u64;
+                for r in
+                    db.iterator_cf(cf, IteratorMode::From(&prefix, rocksdb::Direction::Forward))
+                {
+                    let (k, v) = r?;
+                    if !k.starts_with(&prefix) {
+                        break;
+                    }
+                    // Scored on what was read, common terms have long postings
+                    if opts.budget.exhausted() {
+                        partial = true;
+                        break;
+                    }
+                    seen += 1;
+                    let Some((word, dict, path)) = split_doc(&k[prefix.len()..]) else {
+                        continue;
+                    };
+                    if !opts.keeps_dict(dict) {
+                        continue;
+                    }
+                    if let Ok(p) = bincode::deserialize::<Posting>(&v) {
+                        postings.push((word.to_owned(), dict.to_owned(), path, p));
+                    }
+                }
+                // Databases indexed before frequencies were stored have only the count
+                let df = read_df(&db, cf, term)?.unwrap_or(seen) as f32;
+                let idf = ((n - df + 0.5) / (df + 0.5) + 1.).ln();
+                for (word, dict, path, p) in postings {
+                    let tf = p.tf as f32;
+                    let s = idf * tf * (K1 + 1.)
+                        / (tf + K1 * (1. - B + B * p.len as f32 / avgdl.max(1.)));
+                    *scores.entry((word, dict, path)).or_default() += s;
+                }
+            }
+        }
+
+        // The best sense of each entry
+        let mut best: HashMap<(String, String), (f32, Vec<u16>)> = HashMap::new();
+        for ((word, dict, path), s) in scores {
+            let e = best.entry((word, dict)).or_insert((s, path.clone()));
+            if s > e.0 {
+                *e = (s, path);
+            }
+        }
+        let cap = opts.num.saturating_mul(RANKED_PER_RESULT);
+        let mut heap = BinaryHeap::with_capacity(cap + 1);
+        for ((word, dict), (s, path)) in best {
+            heap.push(Reverse((Score(s), word, dict, path)));
+            if heap.len() > cap {
+                heap.pop();
+            }
+        }
+        let mut hits = vec![];
+        for Reverse((Score(score), word, dict, path)) in heap.into_sorted_vec() {
+            if hits.len() >= opts.num {
+                break;
+            }
+            if opts.budget.exhausted() {
+                partial = true;
+                break;
+            }
+            // Looked up again so the user overlay applies
+            let r = self.retrieve(word.clone());
+            report_damaged(&r.errors);
+            let Some(def) = r.value.as_ref().and_then(|w| w.items.get(&dict)) else {
+                continue;
+            };
+            let Some(sense) = sense_at(def, &path) else {
+                continue;
+            };
+            let text = sense_text(sense);
+            hits.push(FullTextHit {
+                spans: highlight(&text, &terms),
+                word,
+                dict,
+                path,
+                score,
+                text,
+            });
+        }
+        Ok(Partial {
+            value: hits,
+            partial,
+        })
+    }
+
+    /// Indexes every record again, for databases imported before full text search existed.
+    /// Also fills the example concordance.
+    pub fn build_fulltext(&self) -> Result<FullTextStats> {
+        let db = self.db.read().unwrap();
+        let cf = cf_handle(&db, FULLTEXT_CF)?;
+        let examples = cf_handle(&db, EXAMPLES_CF)?;
+        let mut stats = FullTextStats::default();
+        let mut batch = WriteBatch::default();
+        // Postings of entries since deleted or changed would be left behind.
+        // Everything sorts below [STATS_KEY], which is written again at the end.
+        db.delete_range_cf(cf, [0u8], [0xffu8])?;
+        db.delete_range_cf(examples, [0u8], [0xffu8])?;
+        for r in db.iterator(IteratorMode::Start) {
+            let (k, v) = r?;
+            match decode_record(&k, &v) {
+                Ok(rec) => {
+                    put_senses(&mut batch, cf, &rec.word, &rec.dict, &rec.def, &mut stats);
+                    put_examples(&mut batch, examples, &rec.word, &rec.dict, &rec.def);
+                }
+                Err(e) => report_damaged(&[e]),
+            }
+            if batch.len() >= IMPORT_BATCH {
+                db.write(std::mem::take(&mut batch))?;
+            }
+        }
+        for (term, n) in &stats.df {
+            batch.put_cf(cf, df_key(term), bincode::serialize(n)?);
+        }
+        batch.put_cf(cf, STATS_KEY, bincode::serialize(&stats)?);
+        db.write(batch)?;
+        Ok(stats)
+    }
+}
+
+#[test]
+fn test_tokenize() {
+    let terms = |t: &str| -> Vec<String> { tokenize(t).into_iter().map(|t| t.term).collect() };
+    assert_eq!(
+        terms("The fear of open spaces"),
+        vec!["fear", "open", "spaces"]
+    );
+    assert_eq!(terms("隐私权"), vec!["隐私", "私权"]);
+    assert_eq!(
+        terms("保护隐私 privacy"),
+        vec!["保护", "护隐", "隐私", "privacy"]
+    );
+    assert_eq!(terms("私"), vec!["私"]);
+    assert_eq!(terms("don't"), vec!["don't"]);
+
+    let text = "个人隐私 the fear of open spaces";
+    let q: HashSet<String> = terms("隐私 open").into_iter().collect();
+    let spans = highlight(text, &q);
+    let marked: Vec<&str> = spans.iter().map(|(a, b)| &text[*a..*b]).collect();
+    assert_eq!(marked, vec!["隐私", "open"]);
+}
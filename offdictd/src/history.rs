@@ -0,0 +1,172 @@
+//! Lookup history in user.db, so words looked up before and dictionaries actually read rank higher.
+//! Counts and order of last use only, so the same history always gives the same ranking.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocksdb::{Direction, IteratorMode};
+
+use crate::*;
+
+/// Column family of user.db, `w` + headword or `d` + dictionary to [Usage]
+pub const HISTORY_CF: &str = "history";
+const WORD: u8 = b'w';
+const DICT: u8 = b'd';
+/// Places a result moves up per e-fold of lookups of its headword
+const WORD_BOOST: f32 = 2.;
+/// Places a result moves up when all reading happened in its dictionary
+const DICT_BOOST: f32 = 1.;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub count: u32,
+    /// Unix seconds
+    pub last: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct History {
+    /// Most looked up first
+    pub words: Vec<(String, Usage)>,
+    pub dicts: Vec<(String, Usage)>,
+}
+
+fn history_key(kind: u8, name: &str) -> Vec<u8> {
+    let mut k = vec![kind];
+    k.extend(name.as_bytes());
+    k
+}
+
+/// Sort key of a result, lower first. `pos` is where search put it.
+pub fn personal_score(pos: usize, word: Usage, dict_share: f32) -> f32 {
+    pos as f32 - WORD_BOOST * (1. + word.count as f32).ln() - DICT_BOOST * dict_share
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    fn usage(&self, kind: u8, name: &str) -> Result<Usage> {
+        let user = self.user.read().unwrap();
+        let cf = cf_handle(&user, HISTORY_CF)?;
+        Ok(match user.get_cf(cf, history_key(kind, name))? {
+            Some(v) => bincode::deserialize(&v).unwrap_or_default(),
+            None => Usage::default(),
+        })
+    }
+
+    /// Counts a headword as looked up, and each of its dictionaries as read.
+    /// Does nothing with `personalize: false`.
+    pub fn record_lookup(&self, word: &str, dicts: &[String]) -> Result<()> {
+        if !self.personalize {
+            return Ok(());
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let names = [(WORD, word)]
+            .into_iter()
+            .chain(dicts.iter().map(|d| (DICT, d.as_str())));
+        // Held for writing, so lookups recorded at once don't lose each other's counts
+        let user = self.user.write().unwrap();
+        let cf = cf_handle(&user, HISTORY_CF)?;
+        for (kind, name) in names {
+            let key = history_key(kind, name);
+            let mut u: Usage = match user.get_cf(cf, &key)? {
+                Some(v) => bincode::deserialize(&v).unwrap_or_default(),
+                None => Usage::default(),
+            };
+            u.count += 1;
+            u.last = now;
+            user.put_cf(cf, key, bincode::serialize(&u)?)?;
+        }
+        Ok(())
+    }
+
+    /// Most used first
+    fn usages(&self, kind: u8) -> Result<Vec<(String, Usage)>> {
+        let user = self.user.read().unwrap();
+        let cf = cf_handle(&user, HISTORY_CF)?;
+        let mut list = vec![];
+        for r in user.iterator_cf(cf, IteratorMode::From(&[kind], Direction::Forward)) {
+            let (k, v) = r?;
+            if k.first() != Some(&kind) {
+                break;
+            }
+            if let Ok(u) = bincode::deserialize::<Usage>(&v) {
+                list.push((String::from_utf8_lossy(&k[1..]).into_owned(), u));
+            }
+        }
+        list.sort_by(|a, b| (b.1.count, b.1.last, &a.0).cmp(&(a.1.count, a.1.last, &b.0)));
+        Ok(list)
+    }
+
+    pub fn history(&self) -> Result<History> {
+        Ok(History {
+            words: self.usages(WORD)?,
+            dicts: self.usages(DICT)?,
+        })
+    }
+
+    pub fn forget_history(&self) -> Result<()> {
+        let user = self.user.read().unwrap();
+        let cf = cf_handle(&user, HISTORY_CF)?;
+        user.delete_range_cf(cf, [0u8], [0xffu8])?;
+        Ok(())
+    }
+
+    /// [Self::personal_order] within each stage of a search, exact matches up to `exact`,
+    /// inflected ones up to `fuzzy`, then fuzzy and phonetic ones.
+    /// So a fuzzy match never beats an exact one.
+    pub fn personal_stages(
+        &self,
+        res: &mut [DefItemWrapped],
+        exact: usize,
+        fuzzy: usize,
+    ) -> Result<()> {
+        let (head, approx) = res.split_at_mut(fuzzy);
+        let (exact, inflected) = head.split_at_mut(exact);
+        for stage in [exact, inflected, approx] {
+            self.personal_order(stage)?;
+        }
+        Ok(())
+    }
+
+    /// Moves results up by their headword's lookups and their dictionaries' reads.
+    /// Equal scores keep the more recently looked up first, then the order given.
+    pub fn personal_order(&self, res: &mut [DefItemWrapped]) -> Result<()> {
+        if !self.personalize || res.len() < 2 {
+            return Ok(());
+        }
+        let dicts = self.usages(DICT)?;
+        let total: u32 = dicts.iter().map(|(_, u)| u.count).sum();
+        let dict_share = |d: &str| -> f32 {
+            match dicts.iter().find(|(name, _)| name == d) {
+                Some((_, u)) if total > 0 => u.count as f32 / total as f32,
+                _ => 0.,
+            }
+        };
+        let mut scored = vec![];
+        for (pos, w) in res.iter().enumerate() {
+            let word = self.usage(WORD, &w.word)?;
+            let share = w.items.keys().map(|d| dict_share(d)).fold(0., f32::max);
+            scored.push((personal_score(pos, word, share), word.last, pos));
+        }
+        scored.sort_by(|a, b| {
+            a.0.partial_cmp(&b.0)
+                .unwrap_or(Equal)
+                .then(b.1.cmp(&a.1))
+                .then(a.2.cmp(&b.2))
+        });
+        let order: Vec<usize> = scored.into_iter().map(|s| s.2).collect();
+        let mut taken: Vec<DefItemWrapped> = res.iter_mut().map(std::mem::take).collect();
+        for (slot, from) in res.iter_mut().zip(order) {
+            *slot = std::mem::take(&mut taken[from]);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_personal_score() {
+    let never = Usage::default();
+    let often = Usage { count: 10, last: 1 };
+    // Ten lookups make up for a few places, not for the whole list
+    assert!(personal_score(3, often, 0.) < personal_score(0, never, 0.));
+    assert!(personal_score(6, often, 0.) > personal_score(0, never, 0.));
+    assert!(personal_score(1, never, 1.) == personal_score(0, never, 0.));
+}
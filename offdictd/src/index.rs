@@ -0,0 +1,184 @@
+//! The index behind the daemon, picked at runtime by `index` in the config.
+//! `build` writes every kind, so switching needs no rebuild.
+
+use crate::explain::MatchKind;
+use crate::freq::{Frequencies, FREQ_FILE};
+#[cfg(feature = "fst")]
+use crate::fst_index::{exact_and_prefix, fstmmap};
+use crate::topk::Strprox;
+use crate::*;
+
+pub use crate::config::IndexKind;
+
+pub struct Index {
+    pub kind: IndexKind,
+    pub strprox: Option<Strprox>,
+    #[cfg(feature = "fst")]
+    pub fst: Option<fstmmap>,
+    /// Reorders candidates when a frequency list was imported
+    pub freq: Option<Frequencies>,
+    /// How far frequency may outweigh edit distance, `frequency_weight` in the config
+    pub freq_weight: f32,
+}
+
+impl Index {
+    pub fn load_kind(pp: &Path, kind: IndexKind) -> Result<Self> {
+        let strprox = match kind {
+            IndexKind::Strprox | IndexKind::Hybrid => Some(Strprox::load_file(pp)?),
+            IndexKind::Fst => None,
+        };
+        #[cfg(feature = "fst")]
+        let fst = match kind {
+            IndexKind::Fst | IndexKind::Hybrid => {
+                Some(fstmmap::load_file(&pp.with_file_name(fstmmap::FILE_NAME))?)
+            }
+            IndexKind::Strprox => None,
+        };
+        #[cfg(not(feature = "fst"))]
+        if kind != IndexKind::Strprox {
+            bail!(
+                "the {:?} index needs offdictd built with the fst feature",
+                kind
+            );
+        }
+        let freq_path = pp.with_file_name(FREQ_FILE);
+        let freq = if freq_path.exists() {
+            Some(Frequencies::load(&freq_path)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            kind,
+            strprox,
+            #[cfg(feature = "fst")]
+            fst,
+            freq,
+            freq_weight: config::DEFAULT_FREQUENCY_WEIGHT,
+        })
+    }
+
+    fn query_kind(&self, query: &str, opts: &SearchOptions) -> Result<candidates> {
+        match (self.kind, &self.strprox) {
+            (IndexKind::Strprox, Some(strprox)) => return strprox.query(query, opts),
+            #[cfg(feature = "fst")]
+            (IndexKind::Fst, _) => {
+                if let Some(fst) = &self.fst {
+                    return fst.query(query, opts);
+                }
+            }
+            // fst ranks exact and prefix hits well, Strprox catches typos
+            #[cfg(feature = "fst")]
+            (IndexKind::Hybrid, Some(strprox)) => {
+                if let Some(fst) = &self.fst {
+                    let mut cands = exact_and_prefix(fst, query, opts)?;
+                    for c in strprox.query(query, opts)? {
+                        if cands.len() >= opts.num {
+                            break;
+                        }
+                        if !cands.contains(&c) {
+                            cands.push(c);
+                        }
+                    }
+                    return Ok(cands);
+                }
+            }
+            _ => (),
+        }
+        bail!("the {:?} index is not loaded", self.kind)
+    }
+
+    /// Every headword, for benchmarks
+    pub fn words(&self) -> Vec<String> {
+        if let Some(strprox) = &self.strprox {
+            return strprox
+                .yoke
+                .get()
+                .trie
+                .strings
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+        }
+        #[cfg(feature = "fst")]
+        if let Some(fst) = &self.fst {
+            return fst.stream().into_strs().unwrap_or_default();
+        }
+        vec![]
+    }
+}
+
+impl Indexer for Index {
+    const FILE_NAME: &'static str = Strprox::FILE_NAME;
+    #[cfg(feature = "fst")]
+    const EXTRA_FILES: &'static [&'static str] = &[fstmmap::FILE_NAME, FREQ_FILE];
+    #[cfg(not(feature = "fst"))]
+    const EXTRA_FILES: &'static [&'static str] = &[FREQ_FILE];
+    type Param = SearchOptions;
+    fn load_file(pp: &Path) -> Result<Self> {
+        let conf = config::get_config();
+        let mut index = Self::load_kind(pp, conf.index)?;
+        index.freq_weight = conf.frequency_weight;
+        Ok(index)
+    }
+    fn query(&self, query: &str, opts: &SearchOptions) -> Result<candidates> {
+        let Some(freq) = &self.freq else {
+            return self.query_kind(query, opts);
+        };
+        // Frequent words from further down the list may move up
+        let wide = SearchOptions {
+            num: opts.num * 3,
+            ..opts.clone()
+        };
+        let mut cands = freq.rerank(
+            query,
+            self.query_kind(query, &wide)?,
+            opts.mode,
+            self.freq_weight,
+        );
+        cands.truncate(opts.num);
+        Ok(cands)
+    }
+    /// Words must come sorted
+    fn build_all(words: impl IntoIterator<Item = String>, pp: &Path) -> Result<()> {
+        let words: Vec<String> = words.into_iter().collect();
+        #[cfg(feature = "fst")]
+        fstmmap::build_all(
+            words.iter().cloned(),
+            &pp.with_file_name(fstmmap::FILE_NAME),
+        )?;
+        Strprox::build_all(words, pp)
+    }
+    fn for_each_word(&self, f: &mut dyn FnMut(&str) -> bool) {
+        if let Some(strprox) = &self.strprox {
+            return strprox.for_each_word(f);
+        }
+        #[cfg(feature = "fst")]
+        if let Some(fst) = &self.fst {
+            fst.for_each_word(f)
+        }
+    }
+    fn source(&self, kind: MatchKind) -> &'static str {
+        match (self.kind, kind) {
+            (IndexKind::Strprox, _) => Strprox::FILE_NAME,
+            #[cfg(feature = "fst")]
+            (IndexKind::Hybrid, MatchKind::Fuzzy) => Strprox::FILE_NAME,
+            #[cfg(feature = "fst")]
+            _ => fstmmap::FILE_NAME,
+            #[cfg(not(feature = "fst"))]
+            _ => Strprox::FILE_NAME,
+        }
+    }
+    fn frequencies(&self) -> Option<&Frequencies> {
+        self.freq.as_ref()
+    }
+    fn count(&self) -> usize {
+        if let Some(strprox) = &self.strprox {
+            return strprox.count();
+        }
+        #[cfg(feature = "fst")]
+        if let Some(fst) = &self.fst {
+            return fst.count();
+        }
+        0
+    }
+}
@@ -0,0 +1,282 @@
+//! Search by pronunciation. `pronunciation` fields hold IPA like "/ˈprɪvəsi/",
+//! indexed in a loose form for lookups, rhymes and homophones.
+
+use clap::ValueEnum;
+use lazy_regex::regex;
+use rocksdb::{ColumnFamily, IteratorMode, WriteBatch};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::def_bin::Def;
+use crate::fulltext::{is_cjk, pronunciations};
+use crate::normalize::normalize;
+use crate::*;
+
+/// Column family with `kind key \0 other`, the dictionary's IPA as value.
+/// Kinds are [SOUND] (loose IPA, headword), [RHYME] (rhyme, headword) and [WORD] (headword, loose IPA).
+pub const IPA_CF: &str = "ipa";
+const SEP: u8 = 0;
+const SOUND: u8 = b'=';
+const RHYME: u8 = b'~';
+const WORD: u8 = b'@';
+/// Headwords kept per key, the most frequent
+const MAX_PER_KEY: usize = 2000;
+
+const VOWELS: &str = "aeiouyæɑɒɔəɛɜɪʊʌɐɚɝøœɯɤɨʉɘɵɞɶʏ";
+const STRESS: char = 'ˈ';
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SoundKind {
+    /// Headwords pronounced like the IPA typed, or starting so. X-SAMPA letters like `@` and `{` work too.
+    #[default]
+    Ipa,
+    /// Headwords sharing the stressed vowel and what follows, of a headword or IPA
+    Rhyme,
+    /// Headwords pronounced the same as a headword
+    Homophone,
+}
+
+/// The X-SAMPA letters people reach for when they can't type IPA
+fn from_ascii(c: char) -> char {
+    match c {
+        '@' => 'ə',
+        '{' => 'æ',
+        '3' => 'ɜ',
+        'A' => 'ɑ',
+        'Q' => 'ɒ',
+        'O' => 'ɔ',
+        'V' => 'ʌ',
+        'E' => 'ɛ',
+        'I' => 'ɪ',
+        'U' => 'ʊ',
+        'S' => 'ʃ',
+        'Z' => 'ʒ',
+        'T' => 'θ',
+        'D' => 'ð',
+        'N' => 'ŋ',
+        ':' => 'ː',
+        '"' | '\'' => 'ˈ',
+        '%' => 'ˌ',
+        c => c,
+    }
+}
+
+/// Folds what dictionaries write differently: length, secondary stress, syllable breaks,
+/// optional sounds in parentheses, r-colouring and a few look-alike letters. Primary stress stays.
+pub fn loose_ipa(ipa: &str) -> String {
+    let mut s = String::new();
+    for c in ipa.nfd() {
+        match c {
+            'ɹ' | 'ʳ' | 'ɾ' => s.push('r'),
+            'ɡ' => s.push('g'),
+            'ɫ' => s.push('l'),
+            'ɚ' => s.push_str("ər"),
+            'ɝ' => s.push_str("ɜr"),
+            'ʤ' => s.push_str("dʒ"),
+            'ʧ' => s.push_str("tʃ"),
+            STRESS => s.push(STRESS),
+            // Combining marks, tie bars, length and secondary stress
+            '\u{300}'..='\u{36f}' | 'ː' | 'ˑ' | 'ˌ' => (),
+            // Syllable breaks and brackets
+            c if c.is_whitespace() || "-.‿()/[]".contains(c) => (),
+            c => s.extend(c.to_lowercase()),
+        }
+    }
+    s
+}
+
+fn is_vowel(c: char) -> bool {
+    VOWELS.contains(c)
+}
+
+/// Last stressed vowel to the end, "ˈprɪvəsi" gives "ɪvəsi".
+/// Without stress marks, the last vowel and what follows.
+pub fn rhyme(loose: &str) -> Option<String> {
+    let chars: Vec<char> = loose.chars().collect();
+    let start = match chars.iter().rposition(|c| *c == STRESS) {
+        Some(s) => chars[s..]
+            .iter()
+            .position(|c| is_vowel(*c))
+            .map(|v| s + v)?,
+        None => {
+            let last = chars.iter().rposition(|c| is_vowel(*c))?;
+            // Back to the start of a diphthong
+            let mut v = last;
+            while v > 0 && is_vowel(chars[v - 1]) {
+                v -= 1;
+            }
+            v
+        }
+    };
+    Some(chars[start..].iter().filter(|c| **c != STRESS).collect())
+}
+
+/// Without stress, the key for whole pronunciations
+fn sound_key(loose: &str) -> String {
+    loose.chars().filter(|c| *c != STRESS).collect()
+}
+
+/// IPA out of a pronunciation field, "BrE /ˈfɑː(r)/ NAmE /fɑːr/" has two
+pub fn split_ipa(text: &str) -> Vec<String> {
+    let marked: Vec<String> = regex!(r"/([^/]+)/|\[([^\]]+)\]")
+        .captures_iter(text)
+        .filter_map(|c| c.get(1).or(c.get(2)))
+        .map(|m| m.as_str().trim().to_owned())
+        .collect();
+    if marked.is_empty() && !text.trim().is_empty() {
+        vec![text.trim().to_owned()]
+    } else {
+        marked
+    }
+}
+
+fn ipa_key(kind: u8, key: &str, other: &str) -> Vec<u8> {
+    let mut k = Vec::with_capacity(key.len() + other.len() + 2);
+    k.push(kind);
+    k.extend(key.as_bytes());
+    k.push(SEP);
+    k.extend(other.as_bytes());
+    k
+}
+
+/// Chinese headwords carry pinyin instead, see [crate::phonetic]
+pub fn put_ipa(batch: &mut WriteBatch, cf: &ColumnFamily, word: &str, def: &Def) {
+    if word.chars().any(is_cjk) {
+        return;
+    }
+    for ipa in pronunciations(def).iter().flat_map(|p| split_ipa(p)) {
+        let loose = loose_ipa(&ipa);
+        let sound = sound_key(&loose);
+        if sound.is_empty() {
+            continue;
+        }
+        batch.put_cf(cf, ipa_key(SOUND, &sound, word), &ipa);
+        batch.put_cf(cf, ipa_key(WORD, &normalize(word), &sound), &ipa);
+        if let Some(r) = rhyme(&loose) {
+            batch.put_cf(cf, ipa_key(RHYME, &r, word), &ipa);
+        }
+    }
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// `(other, ipa)` under a key, or under every key starting with it
+    fn ipa_scan(&self, kind: u8, key: &str, prefix: bool) -> Result<Vec<(String, String)>> {
+        let db = self.db.read().unwrap();
+        let cf = cf_handle(&db, IPA_CF)?;
+        let mut start = vec![kind];
+        start.extend(key.as_bytes());
+        if !prefix {
+            start.push(SEP);
+        }
+        let mut res = vec![];
+        for r in db.iterator_cf(cf, IteratorMode::From(&start, rocksdb::Direction::Forward)) {
+            let (k, v) = r?;
+            if !k.starts_with(&start) {
+                break;
+            }
+            let Some(sep) = k.iter().rposition(|b| *b == SEP) else {
+                continue;
+            };
+            if let (Ok(other), Ok(ipa)) =
+                (std::str::from_utf8(&k[sep + 1..]), std::str::from_utf8(&v))
+            {
+                let whole = &k[1..sep] == key.as_bytes();
+                res.push((whole, other.to_owned(), ipa.to_owned()));
+            }
+        }
+        // Whole keys before longer ones starting with it, so a rare exact match stays.
+        // Penalties aren't negative, so their bits order like them.
+        res.sort_by_cached_key(|(whole, w, _)| {
+            (!whole, self.freq_penalty(w).to_bits(), w.chars().count())
+        });
+        res.truncate(MAX_PER_KEY);
+        Ok(res.into_iter().map(|(_, w, ipa)| (w, ipa)).collect())
+    }
+
+    /// Loose pronunciations of a headword
+    fn sounds_of(&self, word: &str) -> Result<Vec<(String, String)>> {
+        self.ipa_scan(WORD, &normalize(word), false)
+    }
+
+    /// Headwords with the IPA they matched by, labelled for display
+    pub fn sound_matches(&self, query: &str, kind: SoundKind) -> Result<Vec<(String, String)>> {
+        let mut res: Vec<(String, String)> = vec![];
+        let mut push = |word: String, label: String| {
+            if !res.iter().any(|(w, _)| *w == word) {
+                res.push((word, label));
+            }
+        };
+        match kind {
+            SoundKind::Ipa => {
+                let typed: String = query.chars().map(from_ascii).collect();
+                let key = sound_key(&loose_ipa(&typed));
+                if key.is_empty() {
+                    return Ok(vec![]);
+                }
+                let mut found = self.ipa_scan(SOUND, &key, true)?;
+                // Whole matches first, then by length
+                found.sort_by_key(|(_, ipa)| {
+                    let s = sound_key(&loose_ipa(ipa));
+                    (s != key, s.chars().count())
+                });
+                for (w, ipa) in found {
+                    let label = format!("{} /{}/", w, ipa);
+                    push(w, label);
+                }
+            }
+            SoundKind::Rhyme => {
+                let own = self.sounds_of(query)?;
+                let rhymes: Vec<String> = if own.is_empty() {
+                    let typed: String = query.chars().map(from_ascii).collect();
+                    rhyme(&loose_ipa(&typed)).into_iter().collect()
+                } else {
+                    own.iter()
+                        .filter_map(|(_, ipa)| rhyme(&loose_ipa(ipa)))
+                        .collect()
+                };
+                for r in rhymes {
+                    let mut found = self.ipa_scan(RHYME, &r, false)?;
+                    found.sort_by_key(|(w, _)| w.chars().count());
+                    for (w, ipa) in found {
+                        if normalize(&w) == normalize(query) {
+                            continue;
+                        }
+                        let label = format!("{} /{}/ rhymes with {}", w, ipa, query);
+                        push(w, label);
+                    }
+                }
+            }
+            SoundKind::Homophone => {
+                for (sound, _) in self.sounds_of(query)? {
+                    for (w, ipa) in self.ipa_scan(SOUND, &sound, false)? {
+                        if normalize(&w) == normalize(query) {
+                            continue;
+                        }
+                        let label = format!("{} /{}/ sounds like {}", w, ipa, query);
+                        push(w, label);
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[test]
+fn test_ipa() {
+    assert_eq!(loose_ipa("/ˈprɪvəsi/"), "ˈprɪvəsi");
+    assert_eq!(loose_ipa("ˈfɑː(r)"), loose_ipa("ˈfɑːr"));
+    assert_eq!(loose_ipa("ˈbɝd"), "ˈbɜrd");
+    assert_eq!(rhyme(&loose_ipa("ˈprɪvəsi")), Some("ɪvəsi".to_owned()));
+    assert_eq!(rhyme(&loose_ipa("kæt")), Some("æt".to_owned()));
+    assert_eq!(rhyme(&loose_ipa("ˌɪnfəˈmeɪʃn")), Some("eɪʃn".to_owned()));
+    assert_eq!(rhyme(&loose_ipa("naɪt")), Some("aɪt".to_owned()));
+    assert_eq!(rhyme("st"), None);
+    let typed: String = "\"pr{kt@s".chars().map(from_ascii).collect();
+    assert_eq!(loose_ipa(&typed), "ˈpræktəs");
+    assert_eq!(
+        split_ipa("BrE /ˈfɑː(r)/ NAmE /fɑːr/"),
+        vec!["ˈfɑː(r)".to_owned(), "fɑːr".to_owned()]
+    );
+    assert_eq!(split_ipa("ˈprɪvəsi"), vec!["ˈprɪvəsi".to_owned()]);
+}
@@ -0,0 +1,273 @@
+//! Lemmatization for English, so "running", "geese" and "analysed" bring up their headwords
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use lazy_regex::regex;
+use rocksdb::{ColumnFamily, IteratorMode, WriteBatch};
+
+use crate::def_bin::Def;
+use crate::*;
+
+/// Column family mapping inflected forms to lemmas, harvested from definitions at import
+pub const FORMS_CF: &str = "forms";
+const SEP: u8 = 0;
+
+/// Same format as the file given by `irregular_forms`, one `form<TAB>lemma` per line
+const IRREGULAR: &str = include_str!("../fixtures/irregular.tsv");
+
+pub struct Lemmatizer {
+    irregular: HashMap<String, Vec<String>>,
+}
+
+fn parse_table(text: &str, into: &mut HashMap<String, Vec<String>>) {
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((form, lemma)) = line.split_once('\t') {
+            let lemmas = into.entry(form.trim().to_lowercase()).or_default();
+            let lemma = lemma.trim().to_lowercase();
+            if !lemmas.contains(&lemma) {
+                lemmas.push(lemma);
+            }
+        }
+    }
+}
+
+impl Default for Lemmatizer {
+    fn default() -> Self {
+        let mut irregular = HashMap::new();
+        parse_table(IRREGULAR, &mut irregular);
+        Self { irregular }
+    }
+}
+
+impl Lemmatizer {
+    /// The built-in table, extended by a local file
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut lm = Self::default();
+        parse_table(&std::fs::read_to_string(path)?, &mut lm.irregular);
+        Ok(lm)
+    }
+
+    /// Lemmas the tables give for an irregular form
+    pub fn irregular(&self, word: &str) -> Vec<String> {
+        self.irregular
+            .get(&word.to_lowercase())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Possible lemmas, most likely first. They may not be words at all, callers check.
+    pub fn candidates(&self, word: &str) -> Vec<String> {
+        let lower = word.to_lowercase();
+        let mut res = self.irregular(&lower);
+        for c in suffix_rules(&lower) {
+            if !res.contains(&c) && c != lower {
+                res.push(c);
+            }
+        }
+        res
+    }
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'e' | 'i' | 'o' | 'u')
+}
+
+/// running -> run, stopped -> stop
+fn undouble(stem: &str) -> Option<String> {
+    let mut it = stem.chars().rev();
+    let (a, b) = (it.next()?, it.next()?);
+    if a == b && !is_vowel(a) && !matches!(a, 'l' | 's' | 'z') {
+        Some(stem[..stem.len() - a.len_utf8()].to_owned())
+    } else {
+        None
+    }
+}
+
+/// Stems for the regular inflections of verbs, nouns and adjectives
+pub fn suffix_rules(word: &str) -> Vec<String> {
+    let mut res = vec![];
+    if !word.is_ascii() || word.len() < 4 {
+        return res;
+    }
+    let mut add = |s: String| {
+        if s.len() >= 2 && !res.contains(&s) {
+            res.push(s)
+        }
+    };
+    // -ing, -ed, -er, -est share the same stem handling
+    for suffix in ["ing", "ed", "est", "er"] {
+        if let Some(stem) = word.strip_suffix(suffix) {
+            if suffix != "ing" {
+                if let Some(y) = stem.strip_suffix('i') {
+                    add(format!("{}y", y));
+                }
+            }
+            if let Some(s) = undouble(stem) {
+                add(s);
+            }
+            add(stem.to_owned());
+            add(format!("{}e", stem));
+            // analysed -> analyse, also the American spelling
+            if let Some(ys) = stem.strip_suffix("ys") {
+                add(format!("{}yze", ys));
+            }
+        }
+    }
+    if let Some(stem) = word.strip_suffix("ies") {
+        add(format!("{}y", stem));
+    }
+    if let Some(stem) = word.strip_suffix("ves") {
+        add(format!("{}f", stem));
+        add(format!("{}fe", stem));
+    }
+    if let Some(stem) = word.strip_suffix("es") {
+        if ["s", "x", "z", "ch", "sh", "o"]
+            .iter()
+            .any(|e| stem.ends_with(e))
+        {
+            add(stem.to_owned());
+        }
+    }
+    if let Some(stem) = word.strip_suffix('s') {
+        if !stem.ends_with('s') && !stem.ends_with('u') {
+            add(stem.to_owned());
+        }
+    }
+    res
+}
+
+pub fn form_key(form: &str, lemma: &str) -> Vec<u8> {
+    let mut k = Vec::with_capacity(form.len() + lemma.len() + 1);
+    k.extend(form.as_bytes());
+    k.push(SEP);
+    k.extend(lemma.as_bytes());
+    k
+}
+
+fn harvest_text(text: &str, into: &mut Vec<String>) {
+    let en = regex!(
+        r#"(?i)\b(?:(?:simple )?past tense|past participle|present participle|plural|comparative|superlative|third person singular(?: present)?)(?: form)? of ["“']?([a-z][a-z' -]*[a-z])"#
+    );
+    let cn = regex!(
+        r"([A-Za-z][A-Za-z' -]*[A-Za-z])\s*的\s*(?:过去式|过去分词|现在分词|复数|比较级|最高级|第三人称单数)"
+    );
+    for c in en.captures_iter(text).chain(cn.captures_iter(text)) {
+        let lemma = c[1].trim().to_lowercase();
+        if !into.contains(&lemma) {
+            into.push(lemma);
+        }
+    }
+}
+
+/// Lemmas a definition names for its own headword, like "past tense of run"
+pub fn harvest(def: &Def, into: &mut Vec<String>) {
+    for text in [&def.EN, &def.CN, &def.info].into_iter().flatten() {
+        harvest_text(text, into);
+    }
+    for d in def.definitions.iter().chain(def.groups.iter()).flatten() {
+        harvest(d, into);
+    }
+}
+
+pub fn put_forms(batch: &mut WriteBatch, cf: &ColumnFamily, word: &str, def: &Def) {
+    let mut lemmas = vec![];
+    harvest(def, &mut lemmas);
+    let form = word.to_lowercase();
+    for lemma in lemmas {
+        if lemma != form {
+            batch.put_cf(cf, form_key(&form, &lemma), b"");
+        }
+    }
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// Lemmas the imported dictionaries state for a form
+    pub fn harvested_lemmas(&self, form: &str) -> Result<Vec<String>> {
+        let db = self.db.read().unwrap();
+        let cf = cf_handle(&db, FORMS_CF)?;
+        let mut prefix = form.to_lowercase().into_bytes();
+        prefix.push(SEP);
+        let mut res = vec![];
+        for r in db.iterator_cf(cf, IteratorMode::From(&prefix, rocksdb::Direction::Forward)) {
+            let (k, _) = r?;
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            if let Ok(lemma) = std::str::from_utf8(&k[prefix.len()..]) {
+                res.push(lemma.to_owned());
+            }
+        }
+        Ok(res)
+    }
+
+    /// Headwords that the query is an inflection of, as the dictionaries or the irregular table state it,
+    /// paired with a label like "went → go"
+    pub fn lemma_matches(&self, query: &str) -> Result<Vec<(String, String)>> {
+        let mut lemmas = self.harvested_lemmas(query)?;
+        lemmas.extend(self.lemmatizer.irregular(query));
+        self.lemma_heads(query, lemmas)
+    }
+
+    /// Headwords the suffix rules guess the query inflects, like "running → run".
+    /// The rules also turn "cater" into "cat", so these are ranked with fuzzy matches.
+    pub fn stem_matches(&self, query: &str) -> Result<Vec<(String, String)>> {
+        let lower = query.to_lowercase();
+        let stems = suffix_rules(&lower)
+            .into_iter()
+            .filter(|s| *s != lower)
+            .collect();
+        self.lemma_heads(query, stems)
+    }
+
+    fn lemma_heads(&self, query: &str, lemmas: Vec<String>) -> Result<Vec<(String, String)>> {
+        let mut res: Vec<(String, String)> = vec![];
+        for lemma in lemmas {
+            if let Some(head) = self.normalized_matches(&lemma)?.into_iter().next() {
+                if head != query && !res.iter().any(|(h, _)| *h == head) {
+                    res.push((head.clone(), format!("{} → {}", query, head)));
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[test]
+fn test_lemmas() {
+    let lm = Lemmatizer::default();
+    let has = |w: &str, lemma: &str| {
+        let c = lm.candidates(w);
+        assert!(
+            c.iter().any(|k| k == lemma),
+            "{} should give {}, got {:?}",
+            w,
+            lemma,
+            c
+        );
+    };
+    has("running", "run");
+    has("geese", "goose");
+    has("analysed", "analyse");
+    has("analysed", "analyze");
+    has("studies", "study");
+    has("making", "make");
+    has("stopped", "stop");
+    has("bigger", "big");
+    has("happiest", "happy");
+    has("boxes", "box");
+    has("wolves", "wolf");
+    has("cats", "cat");
+    has("went", "go");
+    has("Children", "child");
+    assert!(lm.candidates("glass").iter().all(|k| k != "glas"));
+
+    let mut found = vec![];
+    harvest_text("past tense of run", &mut found);
+    harvest_text("goose的复数", &mut found);
+    assert_eq!(found, vec!["run".to_owned(), "goose".to_owned()]);
+}
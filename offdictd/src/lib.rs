@@ -6,8 +6,8 @@
 #![feature(associated_type_defaults)]
 #![feature(let_chains)]
 
-use anyhow::bail;
 pub use anyhow::Result;
+use anyhow::{anyhow, bail};
 
 #[cfg(feature = "fst")]
 use fst_index::fstmmap;
@@ -19,6 +19,7 @@ pub use serde::{Deserialize, Serialize};
 
 pub use serde_yaml::{self};
 
+use index::Index;
 pub use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use topk::Strprox;
 
@@ -31,7 +32,7 @@ use std::iter::FromIterator;
 
 use std::marker::PhantomData;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{self, fs, process, vec};
 
 // use bson::{self, Array, Serializer};
@@ -49,10 +50,28 @@ use timed::timed;
 use debug_print::debug_println;
 use std::cmp::Ordering::Equal;
 
+use config::StorageConfig;
 use def_bin::DBKey;
+use examples::{put_examples, EXAMPLES_CF};
+use explain::{other_match, spelling_match, Score};
+use freq::{rank_completions, Frequencies, COMPLETE_SCAN, DEFAULT_COMPLETIONS};
+use fulltext::{add_stats, put_senses, FullTextStats, FULLTEXT_CF};
+use history::HISTORY_CF;
+use ipa::{put_ipa, SoundKind, IPA_CF};
+use lemma::{put_forms, Lemmatizer, FORMS_CF};
 use memmap2::Mmap;
-use rocksdb::{BlockBasedOptions, Options, ReadOptions, SliceTransform, DB as rocks};
+use normalize::{normalize, put_norm, NORM_CF};
+use phonetic::{put_phonetic, PHONETIC_CF};
+use result_cache::{Cached, ResultCache, DEFAULT_RESULT_CACHE};
+use rocksdb::statistics::Ticker;
+use rocksdb::{
+    BlockBasedOptions, Cache, ColumnFamily, ColumnFamilyDescriptor, Options, ReadOptions,
+    SliceTransform, WriteBatch, WriteOptions, DB as rocks, DEFAULT_COLUMN_FAMILY_NAME,
+};
 use serde_ignored;
+use storage::{apply_storage, tune_storage};
+use suggest::DEFAULT_CONFIDENT_SCORE;
+use xref::{put_links, LINKS_CF};
 pub mod topk;
 
 pub type DefItemWrapped = def_bin::WrapperDef;
@@ -62,16 +81,88 @@ pub type DB = rocks;
 pub struct stat {
     pub words: usize,
     pub unique_words: Option<usize>,
+    /// Searches answered from the result cache, and those that weren't
+    pub cache_hits: u64,
+    pub cache_misses: u64,
 }
 
 impl std::fmt::Display for stat {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("Entries in database, {}. ", self.words))?;
         if let Some(uw) = &self.unique_words {
-            f.write_fmt(format_args!("Unique words in index, {}.", uw))
-        } else {
-            Ok(())
+            f.write_fmt(format_args!("Unique words in index, {}. ", uw))?;
         }
+        f.write_fmt(format_args!(
+            "Result cache hits, {}, misses, {}.",
+            self.cache_hits, self.cache_misses
+        ))
+    }
+}
+
+/// Most candidates asked of the index when a dictionary filter keeps dropping them
+const MAX_FILTERED_CANDIDATES: usize = 4096;
+
+/// Records per write batch during imports
+pub const IMPORT_BATCH: usize = 4096;
+
+/// Flushes the memtables when an import returns early, so what it wrote with the WAL off isn't lost
+struct FlushOnError<'a> {
+    db: &'a RwLock<rocks>,
+    armed: bool,
+}
+
+impl<'a> FlushOnError<'a> {
+    fn new(db: &'a RwLock<rocks>) -> Self {
+        Self { db, armed: true }
+    }
+
+    /// The import finished, flushing is left to [Offdict::finish_import]
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for FlushOnError<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        if let Ok(db) = self.db.read() {
+            if let Err(e) = flush_all(&db) {
+                println!("flush after failed import: {}", e);
+            }
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct ImportSummary {
+    pub records: usize,
+    /// Uncompressed key and value bytes written
+    pub bytes: usize,
+    pub elapsed: Duration,
+}
+
+impl std::ops::AddAssign for ImportSummary {
+    fn add_assign(&mut self, rhs: Self) {
+        self.records += rhs.records;
+        self.bytes += rhs.bytes;
+        self.elapsed += rhs.elapsed;
+    }
+}
+
+impl std::fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        let mib = self.bytes as f64 / (1024. * 1024.);
+        f.write_fmt(format_args!(
+            "{} records, {:.1} MiB in {:.1}s, {:.0} records/s, {:.1} MiB/s",
+            self.records,
+            mib,
+            secs,
+            self.records as f64 / secs,
+            mib / secs
+        ))
     }
 }
 
@@ -79,7 +170,21 @@ pub type candidate = String;
 pub type candidates = Vec<candidate>;
 pub struct Offdict<index: Indexer> {
     db: Arc<RwLock<rocks>>,
+    /// User overlay, see [overlay]
+    user: Arc<RwLock<rocks>>,
+    /// Kept for reading statistics
+    db_opts: Options,
+    block_cache: Option<Cache>,
     pub set: Option<index>,
+    pub lemmatizer: Lemmatizer,
+    /// Show the target's definition under entries that only say "see X"
+    pub inline_redirects: bool,
+    /// Keep lookup history and rank by it, see [history]
+    pub personalize: bool,
+    /// Cleared by anything that changes what a search returns
+    pub results: ResultCache,
+    /// Highest score of a fuzzy result shown without suggestions, see [suggest]
+    pub confident_score: f32,
     dirpath: PathBuf,
     /// Request the desktop client to query a word and display it.
     pub set_input: Option<fn(String, bool) -> Result<()>>,
@@ -87,11 +192,23 @@ pub struct Offdict<index: Indexer> {
 
 pub trait Indexer: Sized + 'static {
     const FILE_NAME: &'static str;
+    /// Files next to [Self::FILE_NAME] that belong to the index too
+    const EXTRA_FILES: &'static [&'static str] = &[];
     type Param = ();
     fn load_file(pp: &Path) -> Result<Self>;
-    fn query(&self, query: &str, para: Self::Param) -> Result<candidates>;
+    fn query(&self, query: &str, para: &Self::Param) -> Result<candidates>;
     fn build_all(words: impl IntoIterator<Item = String>, pp: &Path) -> Result<()>;
     fn count(&self) -> usize;
+    /// Calls `f` on every headword until it returns false. Indexes that can't list them do nothing.
+    fn for_each_word(&self, _f: &mut dyn FnMut(&str) -> bool) {}
+    /// Named in the [Match] of candidates it gave
+    fn source(&self, _kind: MatchKind) -> &'static str {
+        Self::FILE_NAME
+    }
+    /// The word frequency list it ranks by, if one was imported
+    fn frequencies(&self) -> Option<&Frequencies> {
+        None
+    }
     fn path(data_dir: &Path) -> PathBuf {
         data_dir.join(Self::FILE_NAME)
     }
@@ -125,29 +242,297 @@ pub fn get_dictname_from_path(path: String) -> Option<String> {
 
 pub trait Diverge {
     type Ix;
-    fn search(&self, query: &str, num: usize, param: bool) -> Result<Vec<DefItemWrapped>>;
+    /// Exact, inflected, then fuzzy matches. Stops early when `opts.budget` runs out,
+    /// flagging the results as partial.
+    fn search(&self, query: &str, opts: &SearchOptions) -> Result<Partial<Vec<DefItemWrapped>>>;
+    /// By pronunciation, see [ipa]
+    fn sounds(
+        &self,
+        query: &str,
+        kind: SoundKind,
+        opts: &SearchOptions,
+    ) -> Result<Vec<DefItemWrapped>>;
+    /// Headwords inside a selected span, see [phrase]
+    fn phrases(&self, text: &str, opts: &SearchOptions) -> Result<Vec<PhraseHit>>;
+    /// What the query may have meant, when `results` of searching it aren't [Offdict::confident].
+    /// None when they are, or nothing better was found. See [suggest]
+    fn did_you_mean(
+        &self,
+        query: &str,
+        results: &[DefItemWrapped],
+        opts: &SearchOptions,
+    ) -> Result<Option<Vec<String>>>;
+    /// Headwords starting with `prefix`, common ones first, without reading their entries
+    fn complete(&self, prefix: &str, num: usize) -> Result<Vec<String>>;
     fn bench(&self, cmd: Commands) -> Result<()> {
         unimplemented!()
     }
 }
 
-impl Diverge for Offdict<Strprox> {
-    type Ix = Strprox;
-    fn search(&self, query: &str, num: usize, _: bool) -> Result<Vec<DefItemWrapped>> {
-        let cands = self.candidates(query, TopkParam::new(num))?;
+impl<Ix: Indexer> Offdict<Ix> {
+    /// A headword's entry as a search result, None when the dictionary filter leaves nothing
+    fn entry(
+        &self,
+        word: String,
+        label: Option<String>,
+        matched: Match,
+        opts: &SearchOptions,
+    ) -> Option<DefItemWrapped> {
+        let r = self.retrieve(word);
+        report_damaged(&r.errors);
+        let mut w = r.value?;
+        w.items.retain(|dict, _| opts.keeps_dict(dict));
+        if w.items.is_empty() {
+            return None;
+        }
+        if self.inline_redirects {
+            self.inline_redirects(&mut w);
+        }
+        Some(DefItemWrapped {
+            label,
+            matched: Some(matched),
+            ..w
+        })
+    }
+}
+
+impl Diverge for Offdict<Index> {
+    type Ix = Index;
+    fn search(&self, query: &str, opts: &SearchOptions) -> Result<Partial<Vec<DefItemWrapped>>> {
+        let opts = &opts.started();
+        if let Some(c) = self.results.get(query, opts) {
+            let mut res = c.defs;
+            self.personal_stages(&mut res, c.exact, c.fuzzy)?;
+            return Ok(Partial {
+                value: res,
+                partial: false,
+            });
+        }
+        let out_of_time = || opts.budget.exhausted();
         let mut res: Vec<DefItemWrapped> = vec![];
-        for s in cands {
-            res.push(self.retrieve(s).unwrap());
+        let mut seen = vec![];
+        let mut push =
+            |word: String, label: Option<String>, matched: Match, res: &mut Vec<DefItemWrapped>| {
+                if res.len() >= opts.num || seen.contains(&word) {
+                    return;
+                }
+                seen.push(word.clone());
+                res.extend(self.entry(word, label, matched, opts));
+            };
+        let spelled = |word: &str, source: &str| {
+            let (kind, distance, span) = spelling_match(query, word, opts.mode);
+            Match {
+                kind,
+                distance,
+                span,
+                source: source.to_owned(),
+                score: Score(distance as f32),
+            }
+        };
+        // Exact matches are always given, whatever the budget
+        for w in self.normalized_matches(query)? {
+            let m = spelled(&w, "normalize");
+            push(w, None, m, &mut res);
+        }
+        let exact = res.len();
+        // Before fuzzy search, which would rank "wet" over "go" for "went"
+        if !out_of_time() {
+            for (w, label) in self.lemma_matches(query)? {
+                let m = other_match(query, &w, MatchKind::Inflection, "lemma");
+                push(w, Some(label), m, &mut res);
+            }
+        }
+        let fuzzy = res.len();
+        // Sounding alike, spelled alike and stems the suffix rules guess compete on score,
+        // scored like fuzzy candidates, for their distance and frequency
+        let mut scored = vec![];
+        if !out_of_time() {
+            for (w, label) in self.stem_matches(query)? {
+                let mut m = other_match(query, &w, MatchKind::Inflection, "suffix rules");
+                // Ranked like a one letter typo, below stated forms and exact or prefix candidates
+                m.score = Score(1.);
+                scored.push((w, Some(label), m));
+            }
+        }
+        if opts.phonetic && !out_of_time() {
+            for (w, label) in self.phonetic_matches(query, &opts.budget)? {
+                let m = other_match(query, &w, MatchKind::Phonetic, "phonetic");
+                scored.push((w, Some(label), m));
+            }
+        }
+        if let Some(index) = &self.set {
+            if let Some(freq) = &index.freq {
+                for (w, _, m) in &mut scored {
+                    m.score.0 += index.freq_weight * freq.penalty(w);
+                }
+            }
+        }
+        scored.sort_by(|a, b| a.2.score.cmp(&b.2.score));
+        if !out_of_time() && res.len() < opts.num {
+            // The dictionary filter drops candidates after the index picked them,
+            // so ask for more until enough are left or the index has no more
+            let filtered = !opts.include.is_empty() || !opts.exclude.is_empty();
+            let mut k = opts.num;
+            loop {
+                let wide = SearchOptions {
+                    num: k,
+                    ..opts.clone()
+                };
+                let cands = self.candidates(query, &wide)?;
+                let exhausted = cands.len() < k;
+                let mut rest = std::mem::take(&mut scored).into_iter().peekable();
+                let mut approx = vec![];
+                for w in cands {
+                    // Without an index they are only the normalized matches
+                    let mut m = spelled(&w, "normalize");
+                    if let Some(index) = &self.set {
+                        m.source = index.source(m.kind).to_owned();
+                        // The same score the frequency list ranked it by
+                        if let Some(freq) = &index.freq {
+                            m.score.0 += index.freq_weight * freq.penalty(&w);
+                        }
+                    }
+                    while let Some(s) = rest.next_if(|s| s.2.score < m.score) {
+                        approx.push(s);
+                    }
+                    approx.push((w, None, m));
+                }
+                approx.extend(rest);
+                for (w, label, m) in approx {
+                    if out_of_time() {
+                        break;
+                    }
+                    push(w, label, m, &mut res);
+                }
+                if !filtered || exhausted || res.len() >= opts.num || out_of_time() {
+                    break;
+                }
+                if k >= MAX_FILTERED_CANDIDATES {
+                    break;
+                }
+                k = (k * 4).min(MAX_FILTERED_CANDIDATES);
+            }
+        }
+        // Out of time for the index
+        for (w, label, m) in scored {
+            push(w, label, m, &mut res);
+        }
+        // Any stage may have been cut short, even with enough results, so those aren't cached
+        let partial = out_of_time();
+        if !partial {
+            self.results.put(
+                query,
+                opts,
+                Cached {
+                    defs: res.clone(),
+                    exact,
+                    fuzzy,
+                },
+            );
+        }
+        self.personal_stages(&mut res, exact, fuzzy)?;
+        Ok(Partial {
+            value: res,
+            partial,
+        })
+    }
+    fn sounds(
+        &self,
+        query: &str,
+        kind: SoundKind,
+        opts: &SearchOptions,
+    ) -> Result<Vec<DefItemWrapped>> {
+        let opts = &opts.started();
+        let mut res = vec![];
+        for (w, label) in self.sound_matches(query, kind)? {
+            if res.len() >= opts.num || opts.budget.exhausted() {
+                break;
+            }
+            let m = other_match(query, &w, MatchKind::Phonetic, "ipa");
+            res.extend(self.entry(w, Some(label), m, opts));
+        }
+        Ok(res)
+    }
+    fn phrases(&self, text: &str, opts: &SearchOptions) -> Result<Vec<PhraseHit>> {
+        let opts = &opts.started();
+        let fuzzy = SearchOptions {
+            num: 3,
+            distance: 2,
+            mode: MatchMode::Word,
+            ..opts.clone()
+        };
+        self.phrase_hits(text, opts, |run| self.candidates(run, &fuzzy))
+    }
+    fn did_you_mean(
+        &self,
+        query: &str,
+        results: &[DefItemWrapped],
+        opts: &SearchOptions,
+    ) -> Result<Option<Vec<String>>> {
+        if self.confident(query, results) {
+            return Ok(None);
+        }
+        // Common words first, weighed as in search
+        let penalty = |w: &str| {
+            let ix = self.set.as_ref()?;
+            Some(ix.freq_weight * ix.freq.as_ref()?.penalty(w))
+        };
+        let found = self.suggestions(query, opts, |w| penalty(w).unwrap_or(0.))?;
+        Ok(Some(found).filter(|f| !f.is_empty()))
+    }
+    fn complete(&self, prefix: &str, num: usize) -> Result<Vec<String>> {
+        let mut res: Vec<String> = vec![];
+        if normalize(prefix).is_empty() {
+            return Ok(res);
+        }
+        let freq = self.set.as_ref().and_then(|ix| ix.freq.as_ref());
+        // In the list's order, skipping words that aren't headwords
+        for w in freq.map(|f| f.with_prefix(prefix)).unwrap_or_default() {
+            if res.len() >= num {
+                return Ok(res);
+            }
+            if let Some(head) = self.normalized_matches(w)?.into_iter().next() {
+                if !res.contains(&head) {
+                    res.push(head);
+                }
+            }
         }
+        // Then headwords the list doesn't have, shorter ones first
+        let rest: candidates = self
+            .prefixed(prefix, COMPLETE_SCAN)?
+            .into_iter()
+            .filter(|w| !res.contains(w))
+            .collect();
+        let more = num - res.len();
+        res.extend(rank_completions(rest, freq, more));
         Ok(res)
     }
     fn bench(&self, cmd: Commands) -> Result<()> {
         match cmd {
-            Commands::bench { delay, short } => {
+            Commands::bench {
+                threads: Some(threads),
+                ..
+            } => {
+                let mut rng = thread_rng();
+                let strvec = self.set.as_ref().unwrap().words();
+                let words: Vec<String> = strvec.choose_multiple(&mut rng, 500).cloned().collect();
+                println!("{} words, each thread queries all of them", words.len());
+                for n in [1, threads.max(1)] {
+                    let (queries, secs) = self.throughput(&words, n)?;
+                    println!(
+                        "{} threads: {} queries in {:.2}s, {:.0}/s",
+                        n,
+                        queries,
+                        secs,
+                        queries as f64 / secs
+                    );
+                }
+            }
+            Commands::bench { delay, short, .. } => {
                 let num = 20;
                 println!("choosing {} words at random", num);
                 let mut rng = thread_rng();
-                let strvec = &self.set.as_ref().unwrap().yoke.get().trie.strings;
+                let strvec = self.set.as_ref().unwrap().words();
                 dbg!(&strvec[2000..2006]);
                 let word = strvec.choose_multiple(&mut rng, num);
                 for q in word {
@@ -155,7 +540,7 @@ impl Diverge for Offdict<Strprox> {
                         continue;
                     }
                     println!("query \"{}\"", q);
-                    self.candidates(&q, TopkParam::new(3))?;
+                    self.candidates(&q, &SearchOptions::top(3))?;
                     if delay {
                         std::thread::sleep(Duration::from_micros(200));
                     }
@@ -168,11 +553,97 @@ impl Diverge for Offdict<Strprox> {
     }
 }
 
+impl Offdict<Index> {
+    /// Queries made and seconds taken, with `threads` querying the index at once
+    fn throughput(&self, words: &[String], threads: usize) -> Result<(usize, f64)> {
+        let start = Instant::now();
+        std::thread::scope(|s| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    s.spawn(|| -> Result<()> {
+                        for q in words {
+                            self.candidates(q, &SearchOptions::top(3))?;
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            for w in workers {
+                w.join().map_err(|_| anyhow!("a bench thread panicked"))??;
+            }
+            Ok::<(), anyhow::Error>(())
+        })?;
+        Ok((words.len() * threads, start.elapsed().as_secs_f64()))
+    }
+}
+
 pub const DBPATH: &str = "dicts.db";
 
+fn report_damaged(errors: &[DecodeError]) {
+    for e in errors {
+        println!("skipped damaged record {}", e);
+    }
+}
+
+/// Tables derived from imported data, stored next to it
+pub const AUX_CFS: &[&str] = &[
+    NORM_CF,
+    FORMS_CF,
+    LINKS_CF,
+    FULLTEXT_CF,
+    EXAMPLES_CF,
+    PHONETIC_CF,
+    IPA_CF,
+];
+
+/// Flushes the records and every table derived from them, imports write them all with the WAL off
+pub fn flush_all(db: &rocks) -> Result<()> {
+    db.flush()?;
+    for name in AUX_CFS {
+        db.flush_cf(cf_handle(db, name)?)?;
+    }
+    Ok(())
+}
+
+pub fn cf_handle<'a>(db: &'a rocks, name: &str) -> Result<&'a ColumnFamily> {
+    db.cf_handle(name)
+        .ok_or_else(|| anyhow!("column family {} is missing", name))
+}
+
+/// Options matching the key layout, plus the `storage` tuning
+pub fn db_options(storage: &StorageConfig) -> (Options, Option<Cache>) {
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    opts.set_prefix_extractor(SliceTransform::create("pre", |bs| DBKey::slice(bs).0, None));
+    let mut tableopts = BlockBasedOptions::default();
+    tableopts.set_index_type(rocksdb::BlockBasedIndexType::HashSearch);
+    let block_cache = apply_storage(storage, &mut opts, &mut tableopts);
+    opts.set_block_based_table_factory(&tableopts);
+    (opts, block_cache)
+}
+
+/// Options for [AUX_CFS], whose keys aren't records: the `storage` tuning without the prefix extractor
+pub fn aux_options(storage: &StorageConfig, cache: Option<&Cache>) -> Options {
+    let mut opts = Options::default();
+    let mut tableopts = BlockBasedOptions::default();
+    tune_storage(storage, &mut opts, &mut tableopts, cache);
+    opts.set_block_based_table_factory(&tableopts);
+    opts
+}
+
+/// Removes imported data and the index. The user overlay stays.
 pub fn rmdata<Ix: Indexer>(data: &Offdict<Ix>) -> Result<()> {
     let dp = &data.dirpath;
-    remove_dir_all(dp)?;
+    remove_dir_all(dp.join(DBPATH))?;
+    let idx = Ix::path(dp);
+    for f in [idx.clone()]
+        .into_iter()
+        .chain(Ix::EXTRA_FILES.iter().map(|f| dp.join(f)))
+    {
+        if f.exists() {
+            fs::remove_file(f)?;
+        }
+    }
 
     Ok(())
 }
@@ -187,36 +658,73 @@ impl<Ix: Indexer> Offdict<Ix> {
     }
 
     pub fn open_db(path: PathBuf) -> Result<Self> {
+        let conf = config::get_config();
+        let mut od = Self::open_db_with(path, &conf.storage)?;
+        if let Some(forms) = &conf.irregular_forms {
+            od.lemmatizer = Lemmatizer::load(forms)?;
+        }
+        od.inline_redirects = conf.inline_redirects;
+        od.personalize = conf.personalize;
+        od.results = ResultCache::new(conf.result_cache);
+        od.confident_score = conf.confident_score;
+        Ok(od)
+    }
+
+    pub fn open_db_with(path: PathBuf, storage: &StorageConfig) -> Result<Self> {
         let db;
         if !path.is_dir() {
             create_dir_all(&path).unwrap();
         }
 
-        let mut opts = Options::default();
-        opts.create_if_missing(true);
-        opts.set_prefix_extractor(SliceTransform::create("pre", |bs| DBKey::slice(bs).0, None));
-        let mut tableopts = BlockBasedOptions::default();
-        tableopts.set_index_type(rocksdb::BlockBasedIndexType::HashSearch);
-        opts.set_block_based_table_factory(&tableopts);
-
-        db = Arc::new(rocks::open(&opts, path.join(DBPATH)).unwrap().into());
-
-        Self::from_db(db, path)
+        let (mut opts, block_cache) = db_options(storage);
+        opts.create_missing_column_families(true);
+        let cfs = [ColumnFamilyDescriptor::new(
+            DEFAULT_COLUMN_FAMILY_NAME,
+            opts.clone(),
+        )]
+        .into_iter()
+        .chain(AUX_CFS.iter().map(|name| {
+            ColumnFamilyDescriptor::new(*name, aux_options(storage, block_cache.as_ref()))
+        }));
+        db = Arc::new(rocks::open_cf_descriptors(&opts, path.join(DBPATH), cfs)?.into());
+        let user_cfs = [
+            ColumnFamilyDescriptor::new(DEFAULT_COLUMN_FAMILY_NAME, opts.clone()),
+            ColumnFamilyDescriptor::new(HISTORY_CF, Options::default()),
+        ];
+        let user = Arc::new(rocks::open_cf_descriptors(&opts, path.join(USERDB), user_cfs)?.into());
+
+        let mut od = Self::from_db(db, user, path)?;
+        od.db_opts = opts;
+        od.block_cache = block_cache;
+        Ok(od)
     }
 
     pub fn load_index(&mut self, data_path: PathBuf) -> Result<()> {
         let idx = data_path.join(Ix::FILE_NAME);
         if idx.exists() {
             self.set = Some(Ix::load_file(&idx)?);
+            self.results.clear();
         }
 
         anyhow::Ok(())
     }
 
-    pub fn from_db(db: Arc<RwLock<rocks>>, path: PathBuf) -> Result<Self> {
+    pub fn from_db(
+        db: Arc<RwLock<rocks>>,
+        user: Arc<RwLock<rocks>>,
+        path: PathBuf,
+    ) -> Result<Self> {
         let od = Offdict {
             db,
+            user,
+            db_opts: Options::default(),
+            block_cache: None,
             set: None,
+            lemmatizer: Lemmatizer::default(),
+            inline_redirects: false,
+            personalize: false,
+            results: ResultCache::new(DEFAULT_RESULT_CACHE),
+            confident_score: DEFAULT_CONFIDENT_SCORE,
             dirpath: path,
             set_input: None,
         };
@@ -224,16 +732,23 @@ impl<Ix: Indexer> Offdict<Ix> {
         Ok(od)
     }
 
-    pub fn candidates(&self, query: &str, param: Ix::Param) -> Result<candidates> {
+    /// Normalized matches come first, as if they were exact
+    pub fn candidates(&self, query: &str, param: &Ix::Param) -> Result<candidates> {
+        let mut cands = self.normalized_matches(query)?;
         if let Some(index) = &self.set {
-            index.query(query, param)
-        } else {
-            Ok(Default::default())
+            for c in index.query(query, param)? {
+                if !cands.contains(&c) {
+                    cands.push(c);
+                }
+            }
         }
+        Ok(cands)
     }
 
-    pub fn retrieve(&self, cand: candidate) -> Option<DefItemWrapped> {
+    /// Entries that fail to decode are skipped and returned alongside.
+    pub fn retrieve(&self, cand: candidate) -> Decoded<Option<DefItemWrapped>> {
         let mut items: BTreeMap<String, def_bin::Def> = BTreeMap::new();
+        let mut errors = vec![];
         for res in self
             .db
             .read()
@@ -242,23 +757,55 @@ impl<Ix: Indexer> Offdict<Ix> {
         {
             if res.is_ok() {
                 let (k, v) = res.unwrap();
-                items.insert(
-                    String::from_utf8(DBKey::slice(&k).1.to_vec()).unwrap(),
-                    Self::deserialize(&v).unwrap(),
-                );
+                match decode_record(&k, &v) {
+                    Ok(rec) => {
+                        items.insert(rec.dict, rec.def);
+                    }
+                    Err(e) => errors.push(e),
+                }
             }
         }
-        if items.len() > 0 {
-            Some(def_bin::WrapperDef { items, word: cand })
-        } else {
-            None
+        self.apply_overlay(&cand, &mut items, &mut errors);
+        Decoded {
+            value: if items.len() > 0 {
+                Some(def_bin::WrapperDef {
+                    items,
+                    word: cand,
+                    label: None,
+                    matched: None,
+                })
+            } else {
+                None
+            },
+            errors,
         }
     }
 
-    pub fn export_all_yaml(&self, path: &str) {
-        let file = File::create(path).expect("Unable to open file");
-        // let mut defs: Vec<DefItemInDB> = vec![];
+    /// Scans the whole database for records that can't be decoded
+    pub fn damaged(&self, dict: Option<&str>) -> Vec<DecodeError> {
+        let mut errors = vec![];
+        for r in self
+            .db
+            .read()
+            .unwrap()
+            .iterator(rocksdb::IteratorMode::Start)
+        {
+            if let Ok((k, v)) = r {
+                if let Err(e) = decode_record(&k, &v) {
+                    if dict.is_none() || e.dict.as_deref() == dict {
+                        errors.push(e);
+                    }
+                }
+            }
+        }
+        errors
+    }
+
+    /// Exports what can be decoded. Returns the records that were left out.
+    pub fn export_all_yaml(&self, path: &str) -> Result<Vec<DecodeError>> {
+        let file = File::create(path)?;
         let mut flat: Vec<DefItem> = vec![];
+        let mut errors = vec![];
         for r in self
             .db
             .read()
@@ -267,12 +814,15 @@ impl<Ix: Indexer> Offdict<Ix> {
         {
             if r.is_ok() {
                 let (k, v) = r.unwrap();
-                // let k: DBKey = Self::deserialize(&k).unwrap();
-                flat.push(Self::deserialize(&v).unwrap());
+                match decode_record(&k, &v) {
+                    Ok(rec) => flat.push(rec.def),
+                    Err(e) => errors.push(e),
+                }
             }
         }
 
-        serde_yaml::to_writer(file, &flat).unwrap();
+        serde_yaml::to_writer(file, &flat)?;
+        Ok(errors)
     }
 
     pub fn import_glob(&self, path: &str) -> Result<()> {
@@ -295,44 +845,107 @@ impl<Ix: Indexer> Offdict<Ix> {
             }
         }
         println!("importing {} files", pendin.len());
-        pendin.into_iter().for_each(|(p, e)| {
-            self.import_from_file(p.as_str(), e.as_str()).unwrap();
-        });
+        let mut total = ImportSummary::default();
+        let guard = FlushOnError::new(&self.db);
+        for (p, e) in pendin {
+            let summary = self.import_from_file(p.as_str(), e.as_str())?;
+            println!("{}: {}", e, summary);
+            total += summary;
+        }
+        guard.disarm();
+
+        let start = Instant::now();
+        self.finish_import()?;
+        println!(
+            "flushed and compacted in {:.1}s",
+            start.elapsed().as_secs_f64()
+        );
+        println!("imported {}", total);
         let stat = self.stat();
         println!("{}", stat);
 
-        self.db.write().unwrap().flush()?;
-
         Ok(())
     }
 
-    pub fn import_from_file(&self, path: &str, dict_name: &str) -> Result<()> {
+    pub fn import_from_file(&self, path: &str, dict_name: &str) -> Result<ImportSummary> {
         let ds = SrcDef::load_yaml(&path, &dict_name)?;
         debug_println!("loaded {} Defs", ds.len());
-        self.import_defs(ds)?;
-
-        Ok(())
+        self.import_defs(ds)
     }
 
     #[timed]
-    pub fn import_defs(&self, defs: Vec<DefItem>) -> Result<()> {
+    pub fn import_defs(&self, defs: Vec<DefItem>) -> Result<ImportSummary> {
         let ws: Vec<DefItemWrapped> = defs.into_iter().map(|d| d.into()).collect();
-        self.import_wrapped(ws);
-        Ok(())
+        self.import_wrapped(ws)
     }
 
-    pub fn import_wrapped(&self, wrapped: Vec<DefItemWrapped>) {
+    /// Writes in batches of [IMPORT_BATCH] with the WAL off.
+    /// Nothing is durable until [Self::finish_import] flushes the memtables, or an error does.
+    pub fn import_wrapped(&self, wrapped: Vec<DefItemWrapped>) -> Result<ImportSummary> {
+        let guard = FlushOnError::new(&self.db);
+        let start = Instant::now();
+        let mut summary = ImportSummary::default();
+        let mut wo = WriteOptions::default();
+        wo.disable_wal(true);
+        let db = self.db.read().unwrap();
+        let norm = cf_handle(&db, NORM_CF)?;
+        let forms = cf_handle(&db, FORMS_CF)?;
+        let links = cf_handle(&db, LINKS_CF)?;
+        let fulltext = cf_handle(&db, FULLTEXT_CF)?;
+        let examples = cf_handle(&db, EXAMPLES_CF)?;
+        let phonetic = cf_handle(&db, PHONETIC_CF)?;
+        let ipa = cf_handle(&db, IPA_CF)?;
+        let mut ft_stats = FullTextStats::default();
+        let mut batch = WriteBatch::default();
         for incoming_w in wrapped {
+            put_norm(&mut batch, norm, &incoming_w.word);
             for (k, v) in incoming_w.items {
-                self.db
-                    .read()
-                    .unwrap()
-                    .put(v.key(), Self::serialize(&v).unwrap())
-                    .unwrap();
+                put_forms(&mut batch, forms, &incoming_w.word, &v);
+                put_links(&mut batch, links, &incoming_w.word, &k, &v);
+                put_senses(
+                    &mut batch,
+                    fulltext,
+                    &incoming_w.word,
+                    &k,
+                    &v,
+                    &mut ft_stats,
+                );
+                put_examples(&mut batch, examples, &incoming_w.word, &k, &v);
+                put_phonetic(&mut batch, phonetic, &incoming_w.word, &v);
+                put_ipa(&mut batch, ipa, &incoming_w.word, &v);
+                let key = v.key();
+                let val = Self::serialize(&v)?;
+                summary.records += 1;
+                summary.bytes += key.len() + val.len();
+                batch.put(key, val);
+                if batch.len() >= IMPORT_BATCH {
+                    db.write_opt(std::mem::take(&mut batch), &wo)?;
+                }
             }
         }
+        if !batch.is_empty() {
+            db.write_opt(batch, &wo)?;
+        }
+        add_stats(&db, fulltext, ft_stats)?;
+        self.results.clear();
+        summary.elapsed = start.elapsed();
+        drop(db);
+        guard.disarm();
+        Ok(summary)
+    }
+
+    /// Persists what the imports wrote and compacts the whole keyspace
+    pub fn finish_import(&self) -> Result<()> {
+        let db = self.db.write().unwrap();
+        flush_all(&db)?;
+        db.compact_range(None::<&[u8]>, None::<&[u8]>);
+        for name in AUX_CFS {
+            db.compact_range_cf(cf_handle(&db, name)?, None::<&[u8]>, None::<&[u8]>);
+        }
+        Ok(())
     }
 
+    /// Counts keys without decoding them, `offdictd damaged` lists those that fail to
     pub fn stat(&self) -> stat {
         let t = self
             .db
@@ -341,8 +954,11 @@ impl<Ix: Indexer> Offdict<Ix> {
             .iterator(rocksdb::IteratorMode::Start)
             .count();
 
+        let (cache_hits, cache_misses) = self.results.counts();
         stat {
             words: t,
+            cache_hits,
+            cache_misses,
             unique_words: if let Some(ref ix) = self.set {
                 Some(ix.count())
             } else {
@@ -351,12 +967,14 @@ impl<Ix: Indexer> Offdict<Ix> {
         }
     }
 
+    /// Headwords with damaged keys are left out of the index
     #[timed]
-    pub fn build_index_from_db(&mut self, txt: bool) -> Result<usize> {
+    pub fn build_index_from_db(&mut self, txt: bool) -> Result<Decoded<usize>> {
         let mut px = self.dirpath.clone();
         px.push(if txt { "words.txt" } else { Ix::FILE_NAME });
 
         let mut set: BTreeSet<String> = BTreeSet::new();
+        let mut errors = vec![];
         for res in self
             .db
             .read()
@@ -365,10 +983,17 @@ impl<Ix: Indexer> Offdict<Ix> {
         {
             if res.is_ok() {
                 let (k, v) = res.unwrap();
-                let word = String::from_utf8(DBKey::slice(&k).0.to_vec()).unwrap();
-                set.insert(word);
+                match decode_key(&k) {
+                    Ok((word, _)) => {
+                        set.insert(word);
+                    }
+                    Err(e) => errors.push(e),
+                }
             }
         }
+        set.extend(self.overlay_words());
+        self.build_norm_table(&set)?;
+        self.build_phonetic()?;
         let c = set.len();
 
         debug_println!("word set len {}", set.len());
@@ -383,9 +1008,10 @@ impl<Ix: Indexer> Offdict<Ix> {
         } else {
             Ix::build_all(sorted, &px)?;
             self.set = Some(Ix::load_file(&px)?);
+            self.results.clear();
         }
 
-        Ok(c)
+        Ok(Decoded { value: c, errors })
     }
 }
 
@@ -590,9 +1216,97 @@ pub enum Commands {
     },
     #[command(about = "Stats")]
     stat {},
+    #[command(about = "Report disk usage and cache efficiency")]
+    storage {},
+    #[command(about = "Take a consistent copy of the database and index, while serving")]
+    backup {
+        /// Must not already hold a backup
+        dir: PathBuf,
+    },
+    #[command(about = "Validate a backup and swap it in. Stop anything using the database first")]
+    restore {
+        dir: PathBuf,
+    },
+    #[command(about = "Personal entries and corrections, kept apart from imported data")]
+    user {
+        #[command(subcommand)]
+        cmd: UserCmd,
+    },
+    #[command(about = "List records that fail to decode")]
+    damaged {
+        /// Only show records from this dictionary
+        #[arg(short = 'd')]
+        dict: Option<String>,
+    },
+    #[command(about = "Cross references from and to a headword")]
+    links {
+        word: String,
+    },
+    #[command(about = "Find headwords by their meaning, like \"the fear of open spaces\"")]
+    reverse {
+        #[arg(required_unless_present = "rebuild")]
+        query: Option<String>,
+        /// Index every record again, for data imported before full text search existed
+        #[arg(long)]
+        rebuild: bool,
+        #[command(flatten)]
+        opts: SearchOptions,
+    },
+    #[command(about = "Example sentences containing a word or phrase, from every dictionary")]
+    examples {
+        #[arg(required_unless_present = "rebuild")]
+        query: Option<String>,
+        /// Index every record again, same as `reverse --rebuild`
+        #[arg(long)]
+        rebuild: bool,
+        #[command(flatten)]
+        opts: SearchOptions,
+    },
+    #[command(about = "Headwords matching c?t, *ology, ^un.*able$, an anagram or a substring")]
+    pattern {
+        query: String,
+        #[arg(short, long, value_enum, default_value_t = PatternKind::Auto)]
+        kind: PatternKind,
+        #[command(flatten)]
+        opts: SearchOptions,
+    },
+    #[command(about = "Headwords by pronunciation: IPA typed, rhymes or homophones")]
+    sounds {
+        /// IPA (X-SAMPA letters like @ and { work too), or a headword for rhymes and homophones
+        query: String,
+        #[arg(short, long, value_enum, default_value_t = SoundKind::Ipa)]
+        kind: SoundKind,
+        #[command(flatten)]
+        opts: SearchOptions,
+    },
+    #[command(about = "Headwords inside a text, longest first, like \"under cultivation\"")]
+    phrases {
+        text: String,
+        #[command(flatten)]
+        opts: SearchOptions,
+    },
+    #[command(about = "Split a text into words, CJK by the longest headwords, and look each up")]
+    tokens {
+        text: String,
+        #[command(flatten)]
+        opts: SearchOptions,
+    },
+    #[command(about = "Headwords starting with a prefix, common ones first")]
+    complete {
+        prefix: String,
+        /// Number of headwords to return
+        #[arg(short = 'n', long, default_value_t = DEFAULT_COMPLETIONS)]
+        num: usize,
+    },
+    #[command(about = "Import a word<TAB>count list to rank common words first")]
+    freq {
+        path: PathBuf,
+    },
     #[command(about = "Fuzzy query (prefix)")]
     lookup {
         query: String,
+        #[command(flatten)]
+        opts: SearchOptions,
     },
     // TODO: bincode import
     // #[command(about = "Convert an yaml file to cbor")]
@@ -616,7 +1330,53 @@ pub enum Commands {
         delay: bool,
         #[arg(short, long)]
         short: bool,
+        /// Query from this many threads at once, and compare throughput with one thread
+        #[arg(short = 'j', long)]
+        threads: Option<usize>,
+    },
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Subcommand)]
+pub enum UserCmd {
+    #[command(about = "Add or replace the definition of a headword in one dictionary")]
+    edit {
+        word: String,
+        #[arg(short = 'd', default_value = USER_DICT)]
+        dict: String,
+        /// Yaml file with the definition, in the import format
+        #[arg(short = 'f', conflicts_with_all = ["en", "cn"])]
+        file: Option<String>,
+        #[arg(long)]
+        en: Option<String>,
+        #[arg(long)]
+        cn: Option<String>,
+    },
+    #[command(about = "Hide a headword, from one dictionary or all of them")]
+    hide {
+        word: String,
+        #[arg(short = 'd', default_value = ALL_DICTS)]
+        dict: String,
     },
+    #[command(about = "Drop an overlay entry, bringing back the imported one")]
+    remove {
+        word: String,
+        #[arg(short = 'd', default_value = USER_DICT)]
+        dict: String,
+    },
+    export {
+        path: String,
+    },
+    import {
+        path: String,
+    },
+    #[command(about = "Most looked up headwords and most read dictionaries")]
+    history {
+        #[arg(short = 'n', default_value_t = 20)]
+        num: usize,
+    },
+    #[command(about = "Clear the lookup history, undoing personalized ranking")]
+    forget {},
 }
 
 #[derive(Parser, Debug)]
@@ -626,7 +1386,10 @@ struct Cli {
     command: Option<Commands>,
 }
 
-pub fn process_cmd<'a, D: Indexer>(db: impl FnOnce() -> Result<&'a mut Offdict<D>>) -> Result<bool>
+pub fn process_cmd<'a, D: Indexer>(
+    data_path: &Path,
+    db: impl FnOnce() -> Result<&'a mut Offdict<D>>,
+) -> Result<bool>
 where
     Offdict<D>: Diverge,
 {
@@ -660,8 +1423,217 @@ where
             println!("{}", s);
             Ok(false)
         }
-        Some(Commands::lookup { query }) => {
-            for d in db()?.search(&query, 1, true)? {
+        Some(Commands::storage {}) => {
+            println!("{}", db()?.storage_report()?);
+            Ok(false)
+        }
+        Some(Commands::backup { dir }) => {
+            let db = db().map_err(|e| {
+                anyhow!(
+                    "{}. If the daemon is running, enter `:backup {}` at its prompt instead",
+                    e,
+                    dir.display()
+                )
+            })?;
+            let manifest = db.backup(&dir)?;
+            println!("{}", serde_yaml::to_string(&manifest)?);
+            Ok(false)
+        }
+        Some(Commands::restore { dir }) => {
+            backup::restore::<D>(&dir, data_path)?;
+            println!("restored from {:?}", dir);
+            Ok(false)
+        }
+        Some(Commands::user { cmd }) => {
+            let db = db()?;
+            match cmd {
+                UserCmd::edit {
+                    word,
+                    dict,
+                    file,
+                    en,
+                    cn,
+                } => {
+                    let def: SrcDef = if let Some(file) = file {
+                        serde_yaml::from_reader(File::open(file)?)?
+                    } else if en.is_some() || cn.is_some() {
+                        SrcDef {
+                            EN: en,
+                            CN: cn,
+                            ..Default::default()
+                        }
+                    } else {
+                        bail!("give a definition with -f, --en or --cn")
+                    };
+                    db.overlay_put(&word, &dict, OverlayEntry::Edit(def.normalize_def().into()))?;
+                    println!(
+                        "{} in {} saved. Run build if the headword is new",
+                        word, dict
+                    );
+                }
+                UserCmd::hide { word, dict } => {
+                    db.overlay_put(&word, &dict, OverlayEntry::Hidden)?;
+                }
+                UserCmd::remove { word, dict } => {
+                    db.overlay_remove(&word, &dict)?;
+                }
+                UserCmd::export { path } => {
+                    report_damaged(&db.export_overlay(&path)?);
+                    println!("exported to {}", path);
+                }
+                UserCmd::import { path } => {
+                    let n = db.import_overlay(&path)?;
+                    println!("{} overlay entries imported", n);
+                }
+                UserCmd::history { num } => {
+                    let h = db.history()?;
+                    for (name, list) in [("headwords", h.words), ("dictionaries", h.dicts)] {
+                        println!("{}", name.bold());
+                        for (w, u) in list.into_iter().take(num) {
+                            println!("  {} {}", u.count, w);
+                        }
+                    }
+                }
+                UserCmd::forget {} => {
+                    db.forget_history()?;
+                    println!("lookup history cleared");
+                }
+            }
+            Ok(false)
+        }
+        Some(Commands::damaged { dict }) => {
+            let errors = db()?.damaged(dict.as_deref());
+            for e in &errors {
+                println!("{}", e);
+            }
+            println!("{} damaged records", errors.len());
+            Ok(false)
+        }
+        Some(Commands::links { word }) => {
+            println!("{}", serde_yaml::to_string(&db()?.links(&word)?)?);
+            Ok(false)
+        }
+        Some(Commands::reverse {
+            query,
+            rebuild,
+            opts,
+        }) => {
+            let db = db()?;
+            if rebuild {
+                let stats = db.build_fulltext()?;
+                println!("indexed {} senses", stats.docs);
+            }
+            let hits = db.fulltext(query.as_deref().unwrap_or_default(), &opts)?;
+            if hits.partial {
+                println!("{}", "out of time, results may be missing".yellow());
+            }
+            for hit in hits.value {
+                println!("{} [{}] {:.2}", hit.word.bold(), hit.dict, hit.score);
+                let mut last = 0;
+                let mut line = String::new();
+                for (a, b) in &hit.spans {
+                    line.push_str(&hit.text[last..*a]);
+                    line.push_str(&(&hit.text[*a..*b]).yellow().to_string());
+                    last = *b;
+                }
+                line.push_str(&hit.text[last..]);
+                println!("  {}", line.replace('\n', "\n  "));
+            }
+            Ok(false)
+        }
+        Some(Commands::examples {
+            query,
+            rebuild,
+            opts,
+        }) => {
+            let db = db()?;
+            if rebuild {
+                let stats = db.build_fulltext()?;
+                println!("indexed {} senses", stats.docs);
+            }
+            let hits = db.examples(query.as_deref().unwrap_or_default(), &opts)?;
+            if hits.partial {
+                println!("{}", "out of time, results may be missing".yellow());
+            }
+            for hit in hits.value {
+                println!("{}", hit.sentence);
+                if let Some(tr) = &hit.translation {
+                    println!("{}", tr);
+                }
+                println!("  {} [{}]", hit.word.bold(), hit.dict);
+            }
+            Ok(false)
+        }
+        Some(Commands::pattern { query, kind, opts }) => {
+            let words = db()?.pattern(&query, kind, &opts)?;
+            if words.partial {
+                println!("{}", "out of time, results may be missing".yellow());
+            }
+            for w in words.value {
+                println!("{}", w);
+            }
+            Ok(false)
+        }
+        Some(Commands::sounds { query, kind, opts }) => {
+            for d in db()?.sounds(&query, kind, &opts)? {
+                if let Some(label) = &d.label {
+                    println!("{}", label.bold());
+                }
+                let list: Vec<SrcDef> = d.vec_human();
+                println!("{}", serde_yaml::to_string::<Vec<SrcDef>>(&list)?)
+            }
+            Ok(false)
+        }
+        Some(Commands::phrases { text, opts }) => {
+            let chars: Vec<char> = text.chars().collect();
+            for hit in db()?.phrases(&text, &opts)? {
+                let found: Vec<String> = hit
+                    .spans
+                    .iter()
+                    .map(|(a, b)| chars[*a..*b].iter().collect())
+                    .collect();
+                let fuzzy = if hit.distance > 0 { " ~" } else { "" };
+                println!(
+                    "{}{} {}",
+                    hit.word.bold(),
+                    fuzzy,
+                    found.join(" … ").yellow()
+                );
+            }
+            Ok(false)
+        }
+        Some(Commands::tokens { text, opts }) => {
+            for (unit, w) in db()?.lookup_units(&text, &opts)? {
+                println!("{} {}", unit.text.yellow(), w.word.bold());
+            }
+            Ok(false)
+        }
+        Some(Commands::complete { prefix, num }) => {
+            for w in db()?.complete(&prefix, num)? {
+                println!("{}", w);
+            }
+            Ok(false)
+        }
+        Some(Commands::freq { path }) => {
+            let n = db()?.import_frequencies(&path)?;
+            println!("ranked {} words", n);
+            Ok(false)
+        }
+        Some(Commands::lookup { query, opts }) => {
+            let (found, suggested) = search_or_suggest(db()?, &query, &opts)?;
+            if !suggested.is_empty() {
+                println!("Did you mean {}?", suggested.join(", ").bold());
+            }
+            if found.partial {
+                println!("{}", "out of time, results may be missing".yellow());
+            }
+            for d in found.value {
+                match &d.matched {
+                    Some(m) if m.kind != MatchKind::Exact => {
+                        println!("{}", format!("{}: {}", d.word, m).dimmed())
+                    }
+                    _ => (),
+                }
                 let list: Vec<SrcDef> = d.vec_human();
                 println!("{}", serde_yaml::to_string::<Vec<SrcDef>>(&list)?)
             }
@@ -674,7 +1646,8 @@ where
         }
         Some(Commands::build { export }) => {
             let c = db()?.build_index_from_db(export)?;
-            println!("built, {} words", c);
+            report_damaged(&c.errors);
+            println!("built, {} words", c.value);
             Ok(true)
         }
         None => Ok(true),
@@ -696,22 +1669,26 @@ fn test_worse_case() -> Result<()> {
     let case = "bring more land under cultivation";
     let conf = crate::config::get_config();
     let db_path = PathBuf::from(conf.data_path.clone());
-    let db = Offdict::<Strprox>::open_db(db_path)?;
+    let db = Offdict::<Index>::open_db(db_path)?;
     println!("testing");
-    db.search(case, 3, false)?;
+    db.search(case, &SearchOptions::top(3))?;
+    db.phrases(case, &SearchOptions::top(3))?;
     Ok(())
 }
 
+use std::convert::Infallible;
 use std::sync::{Arc, RwLock};
 use tokio::{self};
-use warp::Filter;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
 
-pub static mut DB: Option<Offdict<Strprox>> = None;
+pub static mut DB: Option<Offdict<Index>> = None;
 
-pub fn init_db(db_path: PathBuf) -> Result<&'static mut Offdict<Strprox>> {
+pub fn init_db(db_path: PathBuf) -> Result<&'static mut Offdict<Index>> {
     if let Some(_o) = unsafe { &DB } {
     } else {
-        unsafe { DB = Some(Offdict::<Strprox>::open_db(db_path)?) };
+        unsafe { DB = Some(Offdict::<Index>::open_db(db_path)?) };
     }
     Ok(unsafe { DB.as_mut() }.unwrap())
 }
@@ -721,64 +1698,252 @@ pub mod config;
 #[derive(Serialize, Deserialize)]
 pub struct Stat {
     words: u64,
+    cache_hits: u64,
+    cache_misses: u64,
+    /// RocksDB's, since the daemon opened it
+    block_cache_hits: u64,
+    block_cache_misses: u64,
 }
 
+/// Query string of `/q`. Dictionary lists are comma separated.
 #[derive(Deserialize, Default)]
+#[serde(default)]
 pub struct ApiOpts {
-    expensive: bool,
+    num: Option<usize>,
+    distance: Option<usize>,
+    mode: Option<MatchMode>,
+    /// Named like `--dict`
+    dict: Option<String>,
+    exclude: Option<String>,
+    budget_ms: Option<u64>,
+    phonetic: Option<bool>,
+    /// Only for `/pattern`
+    kind: Option<PatternKind>,
+    /// Only for `/sounds`
+    sound: Option<SoundKind>,
+    /// Only for `/q`, results grouped by headword with why each matched
+    explain: Option<bool>,
+}
+
+impl From<ApiOpts> for SearchOptions {
+    fn from(o: ApiOpts) -> Self {
+        let list = |l: Option<String>| -> Vec<String> {
+            l.iter()
+                .flat_map(|l| l.split(','))
+                .map(|d| d.trim().to_owned())
+                .filter(|d| !d.is_empty())
+                .collect()
+        };
+        let def = SearchOptions::default();
+        SearchOptions {
+            num: o.num.unwrap_or(def.num),
+            distance: o.distance.unwrap_or(def.distance),
+            mode: o.mode.unwrap_or(def.mode),
+            include: list(o.dict),
+            exclude: list(o.exclude),
+            budget_ms: o.budget_ms,
+            phonetic: o.phonetic.unwrap_or(def.phonetic),
+            budget: def.budget,
+        }
+    }
 }
 
 #[derive(Deserialize, Default, Serialize)]
 pub struct SetRes;
 
+/// A path parameter that isn't UTF-8 once percent-decoded
+#[derive(Debug)]
+struct BadParam;
+
+impl warp::reject::Reject for BadParam {}
+
+/// The percent-decoded path parameter
+fn decoded_param() -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::path::param::<String>().and_then(|p: String| async move {
+        percent_encoding::percent_decode_str(&p)
+            .decode_utf8()
+            .map(|p| p.to_string())
+            .map_err(|_| warp::reject::custom(BadParam))
+    })
+}
+
+/// The query string, defaults when it's missing or doesn't parse
+fn query_opts() -> impl Filter<Extract = (ApiOpts,), Error = Infallible> + Clone {
+    warp::query::<ApiOpts>().or_else(|_| async { Ok::<_, Infallible>((ApiOpts::default(),)) })
+}
+
+fn error_reply(e: anyhow::Error, status: StatusCode) -> Response {
+    warp::reply::with_status(warp::reply::json(&e.to_string()), status).into_response()
+}
+
+fn json_reply<T: Serialize>(res: Result<T>) -> Response {
+    match res {
+        Ok(v) => warp::reply::json(&v).into_response(),
+        Err(e) => error_reply(e, StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// The body stays a plain list, cut short results are told apart by the header
+fn partial_reply<T: Serialize>(res: Partial<T>) -> Response {
+    warp::reply::with_header(
+        warp::reply::json(&res.value),
+        "x-partial",
+        res.partial.to_string(),
+    )
+    .into_response()
+}
+
+async fn bad_param(r: Rejection) -> std::result::Result<Response, Rejection> {
+    if r.find::<BadParam>().is_some() {
+        return Ok(error_reply(
+            anyhow!("the path isn't UTF-8 once decoded"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    Err(r)
+}
+
 pub async fn serve<Ix: Indexer + Send + Sync + 'static>(db: &'static Offdict<Ix>) -> Result<()>
 where
     Offdict<Ix>: Diverge,
 {
     let lookup = warp::get()
         .and(warp::path("q"))
-        .and(warp::path::param::<String>())
-        .and(
-            warp::query::<ApiOpts>()
-                .map(Some)
-                .or_else(|_| async { Ok::<(Option<ApiOpts>,), std::convert::Infallible>((None,)) }),
-        )
-        .map(move |word: String, opts: Option<ApiOpts>| {
-            let word = percent_encoding::percent_decode_str(&word)
-                .decode_utf8()
-                .unwrap()
-                .to_string();
-            warp::reply::json(&api_q(&db, &word, opts.unwrap_or_default()).unwrap())
+        .and(decoded_param())
+        .and(query_opts())
+        .map(move |word: String, opts: ApiOpts| {
+            let res = if opts.explain == Some(true) {
+                api_explain(&db, &word, opts)
+                    .map(|(res, suggested)| (warp::reply::json(&res.value), res.partial, suggested))
+            } else {
+                api_q(&db, &word, opts)
+                    .map(|(res, suggested)| (warp::reply::json(&res.value), res.partial, suggested))
+            };
+            let (body, partial, suggested) = match res {
+                Ok(r) => r,
+                Err(e) => return error_reply(e, StatusCode::INTERNAL_SERVER_ERROR),
+            };
+            // Percent-encoded and comma separated, empty unless the body is left empty for them
+            let suggested: Vec<String> = suggested
+                .iter()
+                .map(|w| {
+                    percent_encoding::utf8_percent_encode(w, percent_encoding::NON_ALPHANUMERIC)
+                        .to_string()
+                })
+                .collect();
+            warp::reply::with_header(
+                warp::reply::with_header(body, "x-partial", partial.to_string()),
+                "x-did-you-mean",
+                suggested.join(","),
+            )
+            .into_response()
         });
 
-    let stat = warp::get()
-        .and(warp::path("stat"))
-        .map(|| warp::reply::json(&Stat { words: 0 }));
+    let links = warp::get()
+        .and(warp::path("links"))
+        .and(decoded_param())
+        .map(move |word: String| json_reply(db.links(&word)));
+
+    let fulltext = warp::get()
+        .and(warp::path("fulltext"))
+        .and(decoded_param())
+        .and(query_opts())
+        .map(
+            move |query: String, opts: ApiOpts| match db.fulltext(&query, &opts.into()) {
+                Ok(hits) => partial_reply(hits),
+                Err(e) => error_reply(e, StatusCode::INTERNAL_SERVER_ERROR),
+            },
+        );
+
+    let examples = warp::get()
+        .and(warp::path("examples"))
+        .and(decoded_param())
+        .and(query_opts())
+        .map(
+            move |query: String, opts: ApiOpts| match db.examples(&query, &opts.into()) {
+                Ok(hits) => partial_reply(hits),
+                Err(e) => error_reply(e, StatusCode::INTERNAL_SERVER_ERROR),
+            },
+        );
+
+    let pattern = warp::get()
+        .and(warp::path("pattern"))
+        .and(decoded_param())
+        .and(query_opts())
+        .map(move |query: String, opts: ApiOpts| {
+            let kind = opts.kind.unwrap_or_default();
+            // A typo in a regex is the caller's
+            match db.pattern(&query, kind, &opts.into()) {
+                Ok(words) => partial_reply(words),
+                Err(e) => error_reply(e, StatusCode::BAD_REQUEST),
+            }
+        });
+
+    let sounds = warp::get()
+        .and(warp::path("sounds"))
+        .and(decoded_param())
+        .and(query_opts())
+        .map(move |query: String, opts: ApiOpts| {
+            let kind = opts.sound.unwrap_or_default();
+            json_reply(db.sounds(&query, kind, &opts.into()))
+        });
+
+    let phrases = warp::get()
+        .and(warp::path("phrases"))
+        .and(decoded_param())
+        .and(query_opts())
+        .map(move |text: String, opts: ApiOpts| json_reply(db.phrases(&text, &opts.into())));
+
+    let complete = warp::get()
+        .and(warp::path("complete"))
+        .and(decoded_param())
+        .and(query_opts())
+        .map(move |prefix: String, opts: ApiOpts| {
+            // Only `num` applies
+            let num = opts.num.unwrap_or(DEFAULT_COMPLETIONS);
+            json_reply(db.complete(&prefix, num))
+        });
+
+    let stat = warp::get().and(warp::path("stat")).map(move || {
+        let (cache_hits, cache_misses) = db.results.counts();
+        warp::reply::json(&Stat {
+            words: 0,
+            cache_hits,
+            cache_misses,
+            block_cache_hits: db.db_opts.get_ticker_count(Ticker::BlockCacheHit),
+            block_cache_misses: db.db_opts.get_ticker_count(Ticker::BlockCacheMiss),
+        })
+    });
 
     let set = warp::get()
         .and(warp::path("set"))
-        .and(warp::path::param::<String>())
-        .and(
-            warp::query::<ApiOpts>()
-                .map(Some)
-                .or_else(|_| async { Ok::<(Option<ApiOpts>,), std::convert::Infallible>((None,)) }),
-        )
-        .map(move |word: String, opts: Option<ApiOpts>| {
-            let word = percent_encoding::percent_decode_str(&word)
-                .decode_utf8()
-                .unwrap()
-                .to_string();
+        .and(decoded_param())
+        .map(move |word: String| {
             // let mut r: SetRes = SetRes { defs: false };
-            if db.set_input.is_some() {
-                db.set_input.unwrap()(word, false).unwrap();
+            if let Some(set_input) = db.set_input {
+                if let Err(e) = set_input(word, false) {
+                    return error_reply(e, StatusCode::INTERNAL_SERVER_ERROR);
+                }
             }
-            warp::reply::json(&SetRes)
+            warp::reply::json(&SetRes).into_response()
         });
 
     println!("API listening on :3030");
-    Ok(warp::serve(lookup.or(stat).or(set))
-        .run(([0, 0, 0, 0], 3030)) // XXX: this has to be hard coded, who cares
-        .await)
+    Ok(warp::serve(
+        lookup
+            .or(links)
+            .or(fulltext)
+            .or(examples)
+            .or(pattern)
+            .or(sounds)
+            .or(phrases)
+            .or(complete)
+            .or(stat)
+            .or(set)
+            .recover(bad_param),
+    )
+    .run(([0, 0, 0, 0], 3030)) // XXX: this has to be hard coded, who cares
+    .await)
 }
 
 pub async fn repl<Ix: Indexer>(db: &Offdict<Ix>) -> Result<()>
@@ -823,23 +1988,99 @@ pub async fn readline() -> Result<String> {
     Ok(lines.next_line().await?.unwrap())
 }
 
-pub fn api_q<Ix: Indexer>(db: &Offdict<Ix>, query: &str, opts: ApiOpts) -> Result<Vec<SrcDef>>
+pub fn api_q<Ix: Indexer>(
+    db: &Offdict<Ix>,
+    query: &str,
+    opts: ApiOpts,
+) -> Result<(Partial<Vec<SrcDef>>, Vec<String>)>
 where
     Offdict<Ix>: Diverge,
 {
     println!("\nq: {}", query);
 
-    let mut arr = db.search(query, 30, opts.expensive)?;
-    let mut def_list = flatten_human(arr);
+    let (arr, suggested) = search_or_suggest(db, query, &opts.into())?;
+    let mut def_list = flatten_human(arr.value);
 
-    Ok(def_list)
+    Ok((
+        Partial {
+            value: def_list,
+            partial: arr.partial,
+        },
+        suggested,
+    ))
+}
+
+/// Results, or none with what the query may have meant instead of a wrong definition
+pub fn search_or_suggest<Ix: Indexer>(
+    db: &Offdict<Ix>,
+    query: &str,
+    opts: &SearchOptions,
+) -> Result<(Partial<Vec<DefItemWrapped>>, Vec<String>)>
+where
+    Offdict<Ix>: Diverge,
+{
+    let opts = &opts.started();
+    let mut arr = db.search(query, opts)?;
+    match db.did_you_mean(query, &arr.value, opts)? {
+        Some(suggested) => {
+            arr.value.clear();
+            Ok((arr, suggested))
+        }
+        None => Ok((arr, vec![])),
+    }
+}
+
+/// A result of `/q?explain=true`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Explained {
+    pub word: String,
+    pub label: Option<String>,
+    pub matched: Option<Match>,
+    pub defs: Vec<SrcDef>,
+}
+
+pub fn api_explain<Ix: Indexer>(
+    db: &Offdict<Ix>,
+    query: &str,
+    opts: ApiOpts,
+) -> Result<(Partial<Vec<Explained>>, Vec<String>)>
+where
+    Offdict<Ix>: Diverge,
+{
+    println!("\nq: {} (explain)", query);
+
+    let (arr, suggested) = search_or_suggest(db, query, &opts.into())?;
+    let value = arr
+        .value
+        .into_iter()
+        .map(|w| Explained {
+            word: w.word.clone(),
+            label: w.label.clone(),
+            matched: w.matched.clone(),
+            defs: w.vec_human(),
+        })
+        .collect();
+
+    Ok((
+        Partial {
+            value,
+            partial: arr.partial,
+        },
+        suggested,
+    ))
 }
 
 fn respond<Ix: Indexer>(line: &str, db: &Offdict<Ix>) -> Result<bool>
 where
     Offdict<Ix>: Diverge,
 {
-    let mut arr = db.search(line, 2, true)?;
+    if let Some(dir) = line.strip_prefix(":backup ") {
+        let manifest = db.backup(Path::new(dir.trim()))?;
+        println!("{}", serde_yaml::to_string(&manifest)?);
+        return Ok(false);
+    }
+
+    let mut arr = db.search(line, &SearchOptions::top(2))?.value;
 
     println!("{} results", arr.len());
     arr.truncate(2);
@@ -853,13 +2094,42 @@ where
     Ok(false)
 }
 
+pub use decode::*;
 pub use def::*;
-
-use crate::topk::TopkParam;
+pub use overlay::{OverlayEntry, ALL_DICTS, USERDB, USER_DICT};
+
+pub use explain::{Match, MatchKind};
+pub use options::{Budget, MatchMode, Partial, SearchOptions};
+pub use pattern::PatternKind;
+pub use phrase::PhraseHit;
+pub use segment::Unit;
+pub mod backup;
+pub mod decode;
 pub mod def;
 pub mod tests;
 
+pub mod freq;
 #[cfg(feature = "fst")]
 pub mod fst_index;
 
+pub mod fulltext;
+pub mod history;
+
+pub mod index;
+pub mod ipa;
+
 pub mod def_bin;
+pub mod examples;
+pub mod explain;
+pub mod lemma;
+pub mod normalize;
+pub mod options;
+pub mod overlay;
+pub mod pattern;
+pub mod phonetic;
+pub mod phrase;
+pub mod result_cache;
+pub mod segment;
+pub mod storage;
+pub mod suggest;
+pub mod xref;
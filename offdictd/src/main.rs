@@ -19,7 +19,7 @@ fn main() -> Result<()> {
 }
 
 fn newix(db_path: PathBuf) -> Result<()> {
-    process_cmd(|| init_db(db_path.clone()))?;
+    process_cmd(&db_path, || init_db(db_path.clone()))?;
     let db = init_db(db_path)?;
     let rt = tokio::runtime::Runtime::new().unwrap();
     rt.block_on(async move {
@@ -0,0 +1,149 @@
+//! Alternate keys for headwords, so "Café", "cafe" and "ＣＡＦＥ" find each other without fuzzy search
+
+use caseless::default_case_fold_str;
+use rocksdb::{ColumnFamily, IteratorMode, WriteBatch};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::*;
+
+/// Column family mapping normalized forms to headwords
+pub const NORM_CF: &str = "norm";
+const SEP: u8 = 0;
+
+/// Combining Diacritical Marks. Other marks, like kana voicing, change the letter and are kept.
+fn is_diacritic(c: char) -> bool {
+    ('\u{0300}'..='\u{036f}').contains(&c)
+}
+
+fn is_folded(c: char) -> bool {
+    c.is_whitespace()
+        || c.is_ascii_punctuation()
+        || ('\u{2010}'..='\u{2015}').contains(&c) // hyphens and dashes
+        || ('\u{2018}'..='\u{201f}').contains(&c) // curly quotes
+        || c == '\u{00b7}'
+}
+
+/// NFKC (which also takes full-width to half-width), full case folding, diacritic stripping,
+/// then hyphens, spaces and punctuation dropped
+pub fn normalize(word: &str) -> String {
+    // Unlike lowercasing, folds "ß" to "ss" and final "ς" to "σ"
+    let folded: String = default_case_fold_str(&word.nfkc().collect::<String>());
+    let stripped: String = folded
+        .nfd()
+        .filter(|c| !is_diacritic(*c))
+        .nfc()
+        .filter(|c| !is_folded(*c))
+        .collect();
+    if stripped.is_empty() {
+        // headwords made of punctuation only
+        folded
+    } else {
+        stripped
+    }
+}
+
+pub fn norm_key(norm: &str, word: &str) -> Vec<u8> {
+    let mut k = Vec::with_capacity(norm.len() + word.len() + 1);
+    k.extend(norm.as_bytes());
+    k.push(SEP);
+    k.extend(word.as_bytes());
+    k
+}
+
+pub fn put_norm(batch: &mut WriteBatch, cf: &ColumnFamily, word: &str) {
+    batch.put_cf(cf, norm_key(&normalize(word), word), b"");
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// Headwords that normalize to the same form as the query, verbatim matches first
+    pub fn normalized_matches(&self, query: &str) -> Result<candidates> {
+        let db = self.db.read().unwrap();
+        let cf = cf_handle(&db, NORM_CF)?;
+        let mut prefix = normalize(query).into_bytes();
+        prefix.push(SEP);
+        let mut res = vec![];
+        for r in db.iterator_cf(cf, IteratorMode::From(&prefix, rocksdb::Direction::Forward)) {
+            let (k, _) = r?;
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            if let Ok(word) = std::str::from_utf8(&k[prefix.len()..]) {
+                res.push(word.to_owned());
+            }
+        }
+        res.sort_by_key(|w| *w != query);
+        Ok(res)
+    }
+
+    /// Headwords whose normalized form starts with the prefix's, the first `max` in key order
+    pub fn prefixed(&self, prefix: &str, max: usize) -> Result<candidates> {
+        let prefix = normalize(prefix).into_bytes();
+        if prefix.is_empty() {
+            return Ok(vec![]);
+        }
+        let db = self.db.read().unwrap();
+        let cf = cf_handle(&db, NORM_CF)?;
+        let mut res = vec![];
+        for r in db.iterator_cf(cf, IteratorMode::From(&prefix, rocksdb::Direction::Forward)) {
+            let (k, _) = r?;
+            if res.len() >= max || !k.starts_with(&prefix) {
+                break;
+            }
+            let Some(sep) = k[prefix.len()..].iter().position(|b| *b == SEP) else {
+                continue;
+            };
+            if let Ok(word) = std::str::from_utf8(&k[prefix.len() + sep + 1..]) {
+                res.push(word.to_owned());
+            }
+        }
+        Ok(res)
+    }
+
+    /// Rebuilds the table from every headword. Keys of headwords that are gone,
+    /// or were normalized differently by an older version, are dropped.
+    pub fn build_norm_table<'w>(&self, words: impl IntoIterator<Item = &'w String>) -> Result<()> {
+        let db = self.db.read().unwrap();
+        let cf = cf_handle(&db, NORM_CF)?;
+        // No UTF-8 key starts with 0xff
+        db.delete_range_cf(cf, [0u8], [0xffu8])?;
+        let mut batch = WriteBatch::default();
+        for w in words {
+            put_norm(&mut batch, cf, w);
+            if batch.len() >= IMPORT_BATCH {
+                db.write(std::mem::take(&mut batch))?;
+            }
+        }
+        db.write(batch)?;
+        Ok(())
+    }
+
+    /// Adds or drops the key of one headword, as an overlay edit makes it appear or go
+    pub fn sync_norm(&self, word: &str) -> Result<()> {
+        let exists = self.retrieve(word.to_owned()).value.is_some();
+        let db = self.db.read().unwrap();
+        let cf = cf_handle(&db, NORM_CF)?;
+        let key = norm_key(&normalize(word), word);
+        if exists {
+            db.put_cf(cf, key, b"")?;
+        } else {
+            db.delete_cf(cf, key)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_normalize() {
+    assert_eq!(normalize("Café"), "cafe");
+    assert_eq!(normalize("cafe"), "cafe");
+    assert_eq!(normalize("ＣＡＦＥ"), "cafe");
+    assert_eq!(normalize("co-operate"), "cooperate");
+    assert_eq!(normalize("ice cream"), "icecream");
+    assert_eq!(normalize("rock ’n’ roll"), "rocknroll");
+    assert_eq!(normalize("Ångström"), "angstrom");
+    assert_eq!(normalize("隐私"), "隐私");
+    assert_eq!(normalize("がっこう"), "がっこう");
+    assert_eq!(normalize("-"), "-");
+    assert_eq!(normalize("STRASSE"), normalize("straße"));
+    assert_eq!(normalize("ΟΔΟΣ"), normalize("οδός"));
+}
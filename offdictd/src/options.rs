@@ -0,0 +1,179 @@
+//! Knobs for a search, shared by the index, [crate::Diverge], the HTTP API and the CLI
+
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::{Args, ValueEnum};
+
+use crate::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// The query may be the start of a headword, "enviro" finds "environment"
+    #[default]
+    Prefix,
+    /// The edit distance counts against the whole headword
+    Word,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Args)]
+#[serde(default)]
+pub struct SearchOptions {
+    /// Number of headwords to return
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub num: usize,
+    /// Max edit distance for fuzzy matches
+    #[arg(long, default_value_t = 3)]
+    pub distance: usize,
+    #[arg(long, value_enum, default_value_t = MatchMode::Prefix)]
+    pub mode: MatchMode,
+    /// Only these dictionaries. All of them when empty
+    #[arg(short = 'd', long = "dict")]
+    pub include: Vec<String>,
+    /// Never these dictionaries
+    #[arg(short = 'x', long)]
+    pub exclude: Vec<String>,
+    /// Stop collecting results after this many milliseconds
+    #[arg(long)]
+    pub budget_ms: Option<u64>,
+    /// Also headwords that sound like the query, for words only heard
+    #[arg(long)]
+    pub phonetic: bool,
+    #[serde(skip)]
+    #[arg(skip)]
+    pub budget: Budget,
+}
+
+/// Time left for a search, and a way to call it off from another thread. Clones share the flag.
+/// It doesn't change what a search asks for, so options compare and hash the same whatever it holds.
+#[derive(Debug, Clone, Default)]
+pub struct Budget {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Relaxed)
+    }
+
+    /// Cancelled or out of time, what was found so far is all there will be
+    pub fn exhausted(&self) -> bool {
+        self.is_cancelled() || self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+}
+
+impl PartialEq for Budget {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Budget {}
+
+impl Hash for Budget {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+/// Results of a search, with `partial` set when its [Budget] ran out before it was done
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Partial<T> {
+    pub value: T,
+    pub partial: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            num: 10,
+            distance: 3,
+            mode: MatchMode::Prefix,
+            include: vec![],
+            exclude: vec![],
+            budget_ms: None,
+            phonetic: false,
+            budget: Budget::default(),
+        }
+    }
+}
+
+impl SearchOptions {
+    pub fn top(num: usize) -> Self {
+        Self {
+            num,
+            ..Default::default()
+        }
+    }
+
+    pub fn keeps_dict(&self, dict: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|d| d == dict))
+            && !self.exclude.iter().any(|d| d == dict)
+    }
+
+    /// Starts the clock on `budget_ms`, once. Queries made with the returned options share the deadline.
+    pub fn started(&self) -> Self {
+        let mut opts = self.clone();
+        if opts.budget.deadline.is_none() {
+            opts.budget.deadline = self
+                .budget_ms
+                .map(|ms| Instant::now() + Duration::from_millis(ms));
+        }
+        opts
+    }
+}
+
+/// Levenshtein distance over chars
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let sub = prev[j] + (ca != *cb) as usize;
+            cur[j + 1] = sub.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+#[test]
+fn test_options() {
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(edit_distance("隐私", "隐私"), 0);
+
+    let opts = SearchOptions {
+        include: vec!["a".to_owned(), "b".to_owned()],
+        exclude: vec!["b".to_owned()],
+        ..Default::default()
+    };
+    assert!(opts.keeps_dict("a"));
+    assert!(!opts.keeps_dict("b"));
+    assert!(!opts.keeps_dict("c"));
+    assert!(SearchOptions::default().keeps_dict("c"));
+
+    let opts = SearchOptions {
+        budget_ms: Some(60_000),
+        ..Default::default()
+    }
+    .started();
+    let nested = opts.started();
+    assert_eq!(nested.budget.deadline, opts.budget.deadline);
+    assert!(!nested.budget.exhausted());
+    opts.budget.cancel();
+    assert!(nested.budget.exhausted());
+    let asked = SearchOptions {
+        budget_ms: Some(60_000),
+        ..Default::default()
+    };
+    assert_eq!(opts, asked);
+}
@@ -0,0 +1,179 @@
+//! User entries and corrections, kept in their own database so imports and `reset` leave them alone
+
+use std::collections::BTreeMap;
+
+use crate::decode::{DecodeError, DecodeFailure, Decoded};
+use crate::def_bin::{DBKey, Def};
+use crate::*;
+
+pub const USERDB: &str = "user.db";
+/// Dictionary name for entries the user adds
+pub const USER_DICT: &str = "user";
+/// Stands for every dictionary when hiding a headword
+pub const ALL_DICTS: &str = "*";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum OverlayEntry {
+    /// Replaces the imported definition from the same dictionary, or adds one
+    Edit(Def),
+    /// Hides the imported definition, or all of them under [ALL_DICTS]
+    Hidden,
+}
+
+/// Human editable form, for export and import
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OverlayRecord {
+    pub word: String,
+    pub dict: String,
+    #[serde(default)]
+    pub hidden: bool,
+    pub def: Option<SrcDef>,
+}
+
+fn decode_entry(k: &[u8], v: &[u8]) -> Result<(String, String, OverlayEntry), DecodeError> {
+    let (word, dict) = decode_key(k)?;
+    match bincode::deserialize(v) {
+        Ok(entry) => Ok((word, dict, entry)),
+        Err(e) => Err(DecodeError {
+            key: k.to_vec(),
+            dict: Some(dict),
+            reason: DecodeFailure::Value(e),
+        }),
+    }
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    pub fn overlay_put(&self, word: &str, dict: &str, entry: OverlayEntry) -> Result<()> {
+        let entry = match entry {
+            OverlayEntry::Edit(mut def) => {
+                def.word = Some(word.to_owned());
+                def.dictName = Some(dict.to_owned());
+                OverlayEntry::Edit(def)
+            }
+            hidden => hidden,
+        };
+        self.user
+            .read()
+            .unwrap()
+            .put(DBKey::from(word, dict), Self::serialize(&entry)?)?;
+        self.sync_norm(word)?;
+        self.results.clear();
+        Ok(())
+    }
+
+    pub fn overlay_remove(&self, word: &str, dict: &str) -> Result<()> {
+        self.user.read().unwrap().delete(DBKey::from(word, dict))?;
+        self.sync_norm(word)?;
+        self.results.clear();
+        Ok(())
+    }
+
+    /// Overlay entries of one headword, by dictionary
+    pub fn overlay_get(&self, word: &str) -> Decoded<BTreeMap<String, OverlayEntry>> {
+        let mut res = Decoded::new(BTreeMap::new());
+        for r in self
+            .user
+            .read()
+            .unwrap()
+            .prefix_iterator(DBKey::from(word, ""))
+        {
+            if let Ok((k, v)) = r {
+                match decode_entry(&k, &v) {
+                    Ok((_, dict, entry)) => {
+                        res.value.insert(dict, entry);
+                    }
+                    Err(e) => res.errors.push(e),
+                }
+            }
+        }
+        res
+    }
+
+    pub fn overlay_all(&self) -> Decoded<Vec<OverlayRecord>> {
+        let mut res = Decoded::new(vec![]);
+        for r in self
+            .user
+            .read()
+            .unwrap()
+            .iterator(rocksdb::IteratorMode::Start)
+        {
+            if let Ok((k, v)) = r {
+                match decode_entry(&k, &v) {
+                    Ok((word, dict, entry)) => res.value.push(match entry {
+                        OverlayEntry::Edit(def) => OverlayRecord {
+                            word,
+                            dict,
+                            hidden: false,
+                            def: Some(def.into()),
+                        },
+                        OverlayEntry::Hidden => OverlayRecord {
+                            word,
+                            dict,
+                            hidden: true,
+                            def: None,
+                        },
+                    }),
+                    Err(e) => res.errors.push(e),
+                }
+            }
+        }
+        res
+    }
+
+    /// Headwords the user added, so they make it into the index
+    pub fn overlay_words(&self) -> Vec<String> {
+        self.overlay_all()
+            .value
+            .into_iter()
+            .filter(|r| !r.hidden)
+            .map(|r| r.word)
+            .collect()
+    }
+
+    /// Hides first, then applies edits, so an edit survives hiding its headword
+    pub fn apply_overlay(
+        &self,
+        word: &str,
+        items: &mut BTreeMap<String, Def>,
+        errors: &mut Vec<DecodeError>,
+    ) {
+        let mut overlay = self.overlay_get(word);
+        errors.append(&mut overlay.errors);
+        let mut edits = vec![];
+        for (dict, entry) in overlay.value {
+            match entry {
+                OverlayEntry::Hidden if dict == ALL_DICTS => items.clear(),
+                OverlayEntry::Hidden => {
+                    items.remove(&dict);
+                }
+                OverlayEntry::Edit(def) => edits.push((dict, def)),
+            }
+        }
+        items.extend(edits);
+    }
+
+    pub fn export_overlay(&self, path: &str) -> Result<Vec<DecodeError>> {
+        let all = self.overlay_all();
+        serde_yaml::to_writer(File::create(path)?, &all.value)?;
+        Ok(all.errors)
+    }
+
+    pub fn import_overlay(&self, path: &str) -> Result<usize> {
+        let records: Vec<OverlayRecord> = serde_yaml::from_reader(File::open(path)?)?;
+        let n = records.len();
+        for r in records {
+            let entry = match (r.hidden, r.def) {
+                (false, Some(def)) => OverlayEntry::Edit(def.normalize_def().into()),
+                (true, _) => OverlayEntry::Hidden,
+                (false, None) => bail!(
+                    "{} in {} has neither a definition nor hidden",
+                    r.word,
+                    r.dict
+                ),
+            };
+            self.overlay_put(&r.word, &r.dict, entry)?;
+        }
+        Ok(n)
+    }
+}
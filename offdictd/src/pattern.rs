@@ -0,0 +1,173 @@
+//! Headword patterns, for crossword-style recall and listing words with an affix.
+//! `c?t`, `*ology`, `^un.*able$`, anagrams and substrings, by a scan over every headword.
+
+use clap::ValueEnum;
+use regex::{Regex, RegexBuilder};
+
+use crate::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PatternKind {
+    /// Regex when anchored with `^` or `$`, glob with `?`, `*` or `[`, substring otherwise
+    #[default]
+    Auto,
+    /// `?` is one char, `*` any number, `[abc]` and `[!abc]` classes
+    Glob,
+    Regex,
+    /// Same letters in any order, spaces and hyphens ignored
+    Anagram,
+    Contains,
+}
+
+pub enum Pattern {
+    Regex(Regex),
+    /// Sorted letters
+    Anagram(Vec<char>),
+    Contains(String),
+}
+
+/// Anchored regex for a glob, case is ignored
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '?' => re.push('.'),
+            '*' => re.push_str(".*"),
+            '[' => {
+                re.push('[');
+                if chars.next_if(|c| *c == '!' || *c == '^').is_some() {
+                    re.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+            }
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re.push('$');
+    re
+}
+
+fn letters(s: &str) -> Vec<char> {
+    let mut l: Vec<char> = s
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect();
+    l.sort_unstable();
+    l
+}
+
+impl Pattern {
+    pub fn parse(query: &str, kind: PatternKind) -> Result<Self> {
+        let query = query.trim();
+        let kind = match kind {
+            PatternKind::Auto if query.starts_with('^') || query.ends_with('$') => {
+                PatternKind::Regex
+            }
+            PatternKind::Auto if query.contains(['?', '*', '[']) => PatternKind::Glob,
+            PatternKind::Auto => PatternKind::Contains,
+            k => k,
+        };
+        let regex = |re: &str| -> Result<Self> {
+            Ok(Self::Regex(
+                RegexBuilder::new(re).case_insensitive(true).build()?,
+            ))
+        };
+        match kind {
+            PatternKind::Glob => regex(&glob_to_regex(query)),
+            PatternKind::Regex => regex(query),
+            PatternKind::Anagram => Ok(Self::Anagram(letters(query))),
+            _ => Ok(Self::Contains(query.to_lowercase())),
+        }
+    }
+
+    pub fn matches(&self, word: &str) -> bool {
+        match self {
+            Self::Regex(re) => re.is_match(word),
+            Self::Anagram(l) => letters(word) == *l,
+            Self::Contains(s) => word.to_lowercase().contains(s.as_str()),
+        }
+    }
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// Headwords matching the pattern, shortest first
+    pub fn pattern(
+        &self,
+        query: &str,
+        kind: PatternKind,
+        opts: &SearchOptions,
+    ) -> Result<Partial<Vec<String>>> {
+        let pattern = Pattern::parse(query, kind)?;
+        let Some(index) = &self.set else {
+            bail!("no index loaded, run `offdictd build`")
+        };
+        let opts = &opts.started();
+        let mut words = vec![];
+        let mut partial = false;
+        index.for_each_word(&mut |w| {
+            if pattern.matches(w) {
+                words.push(w.to_owned());
+            }
+            partial = opts.budget.exhausted();
+            !partial
+        });
+        words.sort_by(|a, b| (a.chars().count(), a).cmp(&(b.chars().count(), b)));
+
+        let filtered = !opts.include.is_empty() || !opts.exclude.is_empty();
+        let mut res = vec![];
+        for w in words {
+            if res.len() >= opts.num {
+                break;
+            }
+            if filtered {
+                if opts.budget.exhausted() {
+                    partial = true;
+                    break;
+                }
+                let r = self.retrieve(w.clone());
+                report_damaged(&r.errors);
+                if !r
+                    .value
+                    .is_some_and(|v| v.items.keys().any(|d| opts.keeps_dict(d)))
+                {
+                    continue;
+                }
+            }
+            res.push(w);
+        }
+        Ok(Partial {
+            value: res,
+            partial,
+        })
+    }
+}
+
+#[test]
+fn test_pattern() {
+    let m = |q: &str, kind, w: &str| Pattern::parse(q, kind).unwrap().matches(w);
+    assert_eq!(glob_to_regex("c?t"), "^c.t$");
+    assert!(m("c?t", PatternKind::Auto, "cat"));
+    assert!(!m("c?t", PatternKind::Auto, "cart"));
+    assert!(m("*ology", PatternKind::Auto, "Biology"));
+    assert!(m("[!b]at", PatternKind::Auto, "cat"));
+    assert!(!m("[!b]at", PatternKind::Auto, "bat"));
+    assert!(m("^un.*able$", PatternKind::Auto, "unbelievable"));
+    assert!(!m("^un.*able$", PatternKind::Auto, "unable to"));
+    assert!(m("listen", PatternKind::Anagram, "silent"));
+    assert!(m("dormitory", PatternKind::Anagram, "dirty room"));
+    assert!(m("ology", PatternKind::Auto, "ecology"));
+    assert!(m("a.b", PatternKind::Glob, "a.b"));
+    assert!(!m("a.b", PatternKind::Glob, "axb"));
+}
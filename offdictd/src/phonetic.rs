@@ -0,0 +1,770 @@
+//! Sound-alike keys for words we only heard, "nolij" for knowledge.
+//! Double Metaphone for Latin headwords, pinyin without tones for Chinese ones.
+
+use std::collections::HashSet;
+
+use rocksdb::{ColumnFamily, IteratorMode, WriteBatch};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::def_bin::Def;
+use crate::fulltext::{is_cjk, pronunciations};
+use crate::ipa::{put_ipa, IPA_CF};
+use crate::normalize::normalize;
+use crate::options::edit_distance;
+use crate::*;
+
+/// Column family of `key \0 headword`. Metaphone keys are upper case, pinyin lower case.
+pub const PHONETIC_CF: &str = "phonetic";
+const SEP: u8 = 0;
+/// Codes are usually cut at 4, which lumps too many headwords of a big dictionary together
+const MAX_LEN: usize = 6;
+/// Headwords kept, the closest
+const MAX_PER_KEY: usize = 500;
+/// Headwords read per key, a common key of a big dictionary has thousands
+const MAX_SCAN: usize = 5000;
+
+struct Metaphone {
+    w: Vec<char>,
+    /// Without the padding
+    len: usize,
+    slavo_germanic: bool,
+    primary: String,
+    secondary: String,
+}
+
+impl Metaphone {
+    fn at(&self, i: isize) -> char {
+        if i < 0 {
+            '\0'
+        } else {
+            self.w.get(i as usize).copied().unwrap_or('\0')
+        }
+    }
+
+    fn string_at(&self, start: isize, len: usize, list: &[&str]) -> bool {
+        if start < 0 || start as usize + len > self.w.len() {
+            return false;
+        }
+        let s: String = self.w[start as usize..start as usize + len]
+            .iter()
+            .collect();
+        list.contains(&s.as_str())
+    }
+
+    fn is_vowel(&self, i: isize) -> bool {
+        matches!(self.at(i), 'A' | 'E' | 'I' | 'O' | 'U' | 'Y')
+    }
+
+    fn add(&mut self, both: &str) {
+        self.add2(both, both)
+    }
+
+    fn add2(&mut self, primary: &str, secondary: &str) {
+        self.primary.push_str(primary);
+        self.secondary.push_str(secondary);
+    }
+
+    /// Adds the sounds starting at `cur` and returns how many letters they took
+    fn step(&mut self, cur: isize) -> isize {
+        let last = self.len as isize - 1;
+        let next = self.at(cur + 1);
+        let van_von = self.string_at(0, 4, &["VAN ", "VON "]) || self.string_at(0, 3, &["SCH"]);
+        match self.at(cur) {
+            'A' | 'E' | 'I' | 'O' | 'U' | 'Y' => {
+                // Only a leading vowel is heard
+                if cur == 0 {
+                    self.add("A");
+                }
+                1
+            }
+            'B' => {
+                self.add("P");
+                if next == 'B' {
+                    2
+                } else {
+                    1
+                }
+            }
+            'Ç' => {
+                self.add("S");
+                1
+            }
+            'C' => self.step_c(cur),
+            'D' => {
+                if self.string_at(cur, 2, &["DG"]) {
+                    if self.string_at(cur + 2, 1, &["I", "E", "Y"]) {
+                        self.add("J");
+                        3
+                    } else {
+                        self.add("TK");
+                        2
+                    }
+                } else if self.string_at(cur, 2, &["DT", "DD"]) {
+                    self.add("T");
+                    2
+                } else {
+                    self.add("T");
+                    1
+                }
+            }
+            'F' => {
+                self.add("F");
+                if next == 'F' {
+                    2
+                } else {
+                    1
+                }
+            }
+            'G' => self.step_g(cur),
+            'H' => {
+                if (cur == 0 || self.is_vowel(cur - 1)) && self.is_vowel(cur + 1) {
+                    self.add("H");
+                    2
+                } else {
+                    1
+                }
+            }
+            'J' => {
+                if self.string_at(cur, 4, &["JOSE"]) || self.string_at(0, 4, &["SAN "]) {
+                    if (cur == 0 && self.at(cur + 4) == ' ') || self.string_at(0, 4, &["SAN "]) {
+                        self.add("H");
+                    } else {
+                        self.add2("J", "H");
+                    }
+                    return 1;
+                }
+                if cur == 0 {
+                    self.add2("J", "A");
+                } else if self.is_vowel(cur - 1)
+                    && !self.slavo_germanic
+                    && (next == 'A' || next == 'O')
+                {
+                    self.add2("J", "H");
+                } else if cur == last {
+                    self.add2("J", "");
+                } else if !self.string_at(cur + 1, 1, &["L", "T", "K", "S", "N", "M", "B", "Z"])
+                    && !self.string_at(cur - 1, 1, &["S", "K", "L"])
+                {
+                    self.add("J");
+                }
+                if next == 'J' {
+                    2
+                } else {
+                    1
+                }
+            }
+            'K' => {
+                self.add("K");
+                if next == 'K' {
+                    2
+                } else {
+                    1
+                }
+            }
+            'L' => {
+                if next == 'L' {
+                    // Spanish "-illo", "-illa"
+                    if (cur == self.len as isize - 3
+                        && self.string_at(cur - 1, 4, &["ILLO", "ILLA", "ALLE"]))
+                        || ((self.string_at(last - 1, 2, &["AS", "OS"])
+                            || self.string_at(last, 1, &["A", "O"]))
+                            && self.string_at(cur - 1, 4, &["ALLE"]))
+                    {
+                        self.add2("L", "");
+                        return 2;
+                    }
+                    self.add("L");
+                    2
+                } else {
+                    self.add("L");
+                    1
+                }
+            }
+            'M' => {
+                self.add("M");
+                // "dumb", "thumbed"
+                if (self.string_at(cur - 1, 3, &["UMB"])
+                    && (cur + 1 == last || self.string_at(cur + 2, 2, &["ER"])))
+                    || next == 'M'
+                {
+                    2
+                } else {
+                    1
+                }
+            }
+            'N' => {
+                self.add("N");
+                if next == 'N' {
+                    2
+                } else {
+                    1
+                }
+            }
+            'Ñ' => {
+                self.add("N");
+                1
+            }
+            'P' => {
+                if next == 'H' {
+                    self.add("F");
+                    return 2;
+                }
+                self.add("P");
+                if self.string_at(cur + 1, 1, &["P", "B"]) {
+                    2
+                } else {
+                    1
+                }
+            }
+            'Q' => {
+                self.add("K");
+                if next == 'Q' {
+                    2
+                } else {
+                    1
+                }
+            }
+            'R' => {
+                // French "-ier"
+                if cur == last
+                    && !self.slavo_germanic
+                    && self.string_at(cur - 2, 2, &["IE"])
+                    && !self.string_at(cur - 4, 2, &["ME", "MA"])
+                {
+                    self.add2("", "R");
+                } else {
+                    self.add("R");
+                }
+                if next == 'R' {
+                    2
+                } else {
+                    1
+                }
+            }
+            'S' => self.step_s(cur),
+            'T' => {
+                if self.string_at(cur, 4, &["TION"]) || self.string_at(cur, 3, &["TIA", "TCH"]) {
+                    self.add("X");
+                    return 3;
+                }
+                if self.string_at(cur, 2, &["TH"]) || self.string_at(cur, 3, &["TTH"]) {
+                    if self.string_at(cur + 2, 2, &["OM", "AM"]) || van_von {
+                        self.add("T");
+                    } else {
+                        self.add2("0", "T");
+                    }
+                    return 2;
+                }
+                self.add("T");
+                if self.string_at(cur + 1, 1, &["T", "D"]) {
+                    2
+                } else {
+                    1
+                }
+            }
+            'V' => {
+                self.add("F");
+                if next == 'V' {
+                    2
+                } else {
+                    1
+                }
+            }
+            'W' => {
+                if self.string_at(cur, 2, &["WR"]) {
+                    self.add("R");
+                    return 2;
+                }
+                if cur == 0 && (self.is_vowel(cur + 1) || self.string_at(cur, 2, &["WH"])) {
+                    if self.is_vowel(cur + 1) {
+                        self.add2("A", "F");
+                    } else {
+                        self.add("A");
+                    }
+                }
+                // Polish "-ewski"
+                if (cur == last && self.is_vowel(cur - 1))
+                    || self.string_at(cur - 1, 5, &["EWSKI", "EWSKY", "OWSKI", "OWSKY"])
+                    || self.string_at(0, 3, &["SCH"])
+                {
+                    self.add2("", "F");
+                    return 1;
+                }
+                if self.string_at(cur, 4, &["WICZ", "WITZ"]) {
+                    self.add2("TS", "FX");
+                    return 4;
+                }
+                1
+            }
+            'X' => {
+                // French "-eau", "-oux"
+                if !(cur == last
+                    && (self.string_at(cur - 3, 3, &["IAU", "EAU"])
+                        || self.string_at(cur - 2, 2, &["AU", "OU"])))
+                {
+                    self.add("KS");
+                }
+                if self.string_at(cur + 1, 1, &["C", "X"]) {
+                    2
+                } else {
+                    1
+                }
+            }
+            'Z' => {
+                if next == 'H' {
+                    self.add("J");
+                    return 2;
+                }
+                if self.string_at(cur + 1, 2, &["ZO", "ZI", "ZA"])
+                    || (self.slavo_germanic && cur > 0 && self.at(cur - 1) != 'T')
+                {
+                    self.add2("S", "TS");
+                } else {
+                    self.add("S");
+                }
+                if next == 'Z' {
+                    2
+                } else {
+                    1
+                }
+            }
+            _ => 1,
+        }
+    }
+
+    fn step_c(&mut self, cur: isize) -> isize {
+        // Germanic "bacher", "macher"
+        if cur > 1
+            && !self.is_vowel(cur - 2)
+            && self.string_at(cur - 1, 3, &["ACH"])
+            && self.at(cur + 2) != 'I'
+            && (self.at(cur + 2) != 'E' || self.string_at(cur - 2, 6, &["BACHER", "MACHER"]))
+        {
+            self.add("K");
+            return 2;
+        }
+        if cur == 0 && self.string_at(cur, 6, &["CAESAR"]) {
+            self.add("S");
+            return 2;
+        }
+        if self.string_at(cur, 4, &["CHIA"]) {
+            self.add("K");
+            return 2;
+        }
+        if self.string_at(cur, 2, &["CH"]) {
+            if cur > 0 && self.string_at(cur, 4, &["CHAE"]) {
+                self.add2("K", "X");
+                return 2;
+            }
+            // Greek "chorus", "character"
+            if cur == 0
+                && (self.string_at(cur + 1, 5, &["HARAC", "HARIS"])
+                    || self.string_at(cur + 1, 3, &["HOR", "HYM", "HIA", "HEM"]))
+                && !self.string_at(0, 5, &["CHORE"])
+            {
+                self.add("K");
+                return 2;
+            }
+            if self.string_at(0, 4, &["VAN ", "VON "])
+                || self.string_at(0, 3, &["SCH"])
+                || self.string_at(cur - 2, 6, &["ORCHES", "ARCHIT", "ORCHID"])
+                || self.string_at(cur + 2, 1, &["T", "S"])
+                || ((self.string_at(cur - 1, 1, &["A", "O", "U", "E"]) || cur == 0)
+                    && self.string_at(
+                        cur + 2,
+                        1,
+                        &["L", "R", "N", "M", "B", "H", "F", "V", "W", " "],
+                    ))
+            {
+                self.add("K");
+            } else if cur > 0 {
+                if self.string_at(0, 2, &["MC"]) {
+                    self.add("K");
+                } else {
+                    self.add2("X", "K");
+                }
+            } else {
+                self.add("X");
+            }
+            return 2;
+        }
+        if self.string_at(cur, 2, &["CZ"]) && !self.string_at(cur - 2, 4, &["WICZ"]) {
+            self.add2("S", "X");
+            return 2;
+        }
+        if self.string_at(cur + 1, 3, &["CIA"]) {
+            self.add("X");
+            return 3;
+        }
+        // "accident", but not "McClellan"
+        if self.string_at(cur, 2, &["CC"]) && !(cur == 1 && self.at(0) == 'M') {
+            if self.string_at(cur + 2, 1, &["I", "E", "H"]) && !self.string_at(cur + 2, 2, &["HU"])
+            {
+                if (cur == 1 && self.at(cur - 1) == 'A')
+                    || self.string_at(cur - 1, 5, &["UCCEE", "UCCES"])
+                {
+                    self.add("KS");
+                } else {
+                    self.add("X");
+                }
+                return 3;
+            }
+            self.add("K");
+            return 2;
+        }
+        if self.string_at(cur, 2, &["CK", "CG", "CQ"]) {
+            self.add("K");
+            return 2;
+        }
+        if self.string_at(cur, 2, &["CI", "CE", "CY"]) {
+            if self.string_at(cur, 3, &["CIO", "CIE", "CIA"]) {
+                self.add2("S", "X");
+            } else {
+                self.add("S");
+            }
+            return 2;
+        }
+        self.add("K");
+        if self.string_at(cur + 1, 2, &[" C", " Q", " G"]) {
+            3
+        } else if self.string_at(cur + 1, 1, &["C", "K", "Q"])
+            && !self.string_at(cur + 1, 2, &["CE", "CI"])
+        {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn step_g(&mut self, cur: isize) -> isize {
+        let next = self.at(cur + 1);
+        if next == 'H' {
+            if cur > 0 && !self.is_vowel(cur - 1) {
+                self.add("K");
+                return 2;
+            }
+            if cur == 0 {
+                // "ghislane", "ghost"
+                if self.at(cur + 2) == 'I' {
+                    self.add("J");
+                } else {
+                    self.add("K");
+                }
+                return 2;
+            }
+            // Silent in "hugh", "bough", "broughton"
+            if (cur > 1 && self.string_at(cur - 2, 1, &["B", "H", "D"]))
+                || (cur > 2 && self.string_at(cur - 3, 1, &["B", "H", "D"]))
+                || (cur > 3 && self.string_at(cur - 4, 1, &["B", "H"]))
+            {
+                return 2;
+            }
+            // "laugh", "tough"
+            if cur > 2
+                && self.at(cur - 1) == 'U'
+                && self.string_at(cur - 3, 1, &["C", "G", "L", "R", "T"])
+            {
+                self.add("F");
+            } else if self.at(cur - 1) != 'I' {
+                self.add("K");
+            }
+            return 2;
+        }
+        if next == 'N' {
+            if cur == 1 && self.is_vowel(0) && !self.slavo_germanic {
+                self.add2("KN", "N");
+            } else if !self.string_at(cur + 2, 2, &["EY"]) && !self.slavo_germanic {
+                self.add2("N", "KN");
+            } else {
+                self.add("KN");
+            }
+            return 2;
+        }
+        if self.string_at(cur + 1, 2, &["LI"]) && !self.slavo_germanic {
+            self.add2("KL", "L");
+            return 2;
+        }
+        let soft = [
+            "ES", "EP", "EB", "EL", "EY", "IB", "IL", "IN", "IE", "EI", "ER",
+        ];
+        if cur == 0 && (next == 'Y' || self.string_at(cur + 1, 2, &soft)) {
+            self.add2("K", "J");
+            return 2;
+        }
+        // "-ger", "-gy", but not "danger"
+        if (self.string_at(cur + 1, 2, &["ER"]) || next == 'Y')
+            && !self.string_at(0, 6, &["DANGER", "RANGER", "MANGER"])
+            && !self.string_at(cur - 1, 1, &["E", "I"])
+            && !self.string_at(cur - 1, 3, &["RGY", "OGY"])
+        {
+            self.add2("K", "J");
+            return 2;
+        }
+        if self.string_at(cur + 1, 1, &["E", "I", "Y"])
+            || self.string_at(cur - 1, 4, &["AGGI", "OGGI"])
+        {
+            if self.string_at(0, 4, &["VAN ", "VON "])
+                || self.string_at(0, 3, &["SCH"])
+                || self.string_at(cur + 1, 2, &["ET"])
+            {
+                self.add("K");
+            } else if self.string_at(cur + 1, 4, &["IER "]) {
+                self.add("J");
+            } else {
+                self.add2("J", "K");
+            }
+            return 2;
+        }
+        self.add("K");
+        if next == 'G' {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn step_s(&mut self, cur: isize) -> isize {
+        let last = self.len as isize - 1;
+        // "island", "carlisle"
+        if self.string_at(cur - 1, 3, &["ISL", "YSL"]) {
+            return 1;
+        }
+        if cur == 0 && self.string_at(cur, 5, &["SUGAR"]) {
+            self.add2("X", "S");
+            return 1;
+        }
+        if self.string_at(cur, 2, &["SH"]) {
+            if self.string_at(cur + 1, 4, &["HEIM", "HOEK", "HOLM", "HOLZ"]) {
+                self.add("S");
+            } else {
+                self.add("X");
+            }
+            return 2;
+        }
+        if self.string_at(cur, 3, &["SIO", "SIA"]) || self.string_at(cur, 4, &["SIAN"]) {
+            if self.slavo_germanic {
+                self.add("S");
+            } else {
+                self.add2("S", "X");
+            }
+            return 3;
+        }
+        if (cur == 0 && self.string_at(cur + 1, 1, &["M", "N", "L", "W"]))
+            || self.string_at(cur + 1, 1, &["Z"])
+        {
+            self.add2("S", "X");
+            return if self.string_at(cur + 1, 1, &["Z"]) {
+                2
+            } else {
+                1
+            };
+        }
+        if self.string_at(cur, 2, &["SC"]) {
+            if self.at(cur + 2) == 'H' {
+                // Dutch "school", "schenker"
+                if self.string_at(cur + 3, 2, &["OO", "ER", "EN", "UY", "ED", "EM"]) {
+                    if self.string_at(cur + 3, 2, &["ER", "EN"]) {
+                        self.add2("X", "SK");
+                    } else {
+                        self.add("SK");
+                    }
+                } else if cur == 0 && !self.is_vowel(3) && self.at(3) != 'W' {
+                    self.add2("X", "S");
+                } else {
+                    self.add("X");
+                }
+                return 3;
+            }
+            if self.string_at(cur + 2, 1, &["I", "E", "Y"]) {
+                self.add("S");
+            } else {
+                self.add("SK");
+            }
+            return 3;
+        }
+        // French "-ais", "-ois"
+        if cur == last && self.string_at(cur - 2, 2, &["AI", "OI"]) {
+            self.add2("", "S");
+        } else {
+            self.add("S");
+        }
+        if self.string_at(cur + 1, 1, &["S", "Z"]) {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+/// Primary and alternate Double Metaphone codes, after Lawrence Philips
+pub fn double_metaphone(word: &str) -> (String, String) {
+    let upper = word.trim().to_uppercase();
+    let mut w: Vec<char> = upper.chars().collect();
+    let len = w.len();
+    // Rules look a few letters ahead and test for the end with a space
+    w.extend([' '; 5]);
+    let mut m = Metaphone {
+        slavo_germanic: upper.contains('W')
+            || upper.contains('K')
+            || upper.contains("CZ")
+            || upper.contains("WITZ"),
+        w,
+        len,
+        primary: String::new(),
+        secondary: String::new(),
+    };
+    let mut cur: isize = 0;
+    if m.string_at(0, 2, &["GN", "KN", "PN", "WR", "PS"]) {
+        cur += 1;
+    }
+    if m.at(0) == 'X' {
+        m.add("S");
+        cur += 1;
+    }
+    while (cur as usize) < len && (m.primary.len() < MAX_LEN || m.secondary.len() < MAX_LEN) {
+        cur += m.step(cur);
+    }
+    m.primary.truncate(MAX_LEN);
+    m.secondary.truncate(MAX_LEN);
+    (m.primary, m.secondary)
+}
+
+/// "yǐn sī", "yin3 si1" and "[yin si]" all become "yinsi". None for text that isn't pinyin.
+pub fn pinyin_key(text: &str) -> Option<String> {
+    let mut key = String::new();
+    for c in text.nfd() {
+        match c {
+            'a'..='z' => key.push(c),
+            'A'..='Z' => key.push(c.to_ascii_lowercase()),
+            // Tone marks, tone numbers and syllable separators
+            '\u{300}'..='\u{36f}' | '0'..='9' | ' ' | '\'' | '’' | '-' | '[' | ']' | '/' => (),
+            _ => return None,
+        }
+    }
+    (!key.is_empty()).then_some(key)
+}
+
+/// Keys a headword is found by. Chinese ones need the pinyin of the record.
+pub fn phonetic_keys(word: &str, def: &Def) -> Vec<String> {
+    let mut keys = vec![];
+    if word.chars().any(is_cjk) {
+        keys.extend(pronunciations(def).iter().filter_map(|p| pinyin_key(p)));
+    } else {
+        let (primary, secondary) = double_metaphone(word);
+        keys.push(primary);
+        keys.push(secondary);
+    }
+    keys.retain(|k| !k.is_empty());
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+fn phonetic_key(key: &str, word: &str) -> Vec<u8> {
+    let mut k = Vec::with_capacity(key.len() + word.len() + 1);
+    k.extend(key.as_bytes());
+    k.push(SEP);
+    k.extend(word.as_bytes());
+    k
+}
+
+pub fn put_phonetic(batch: &mut WriteBatch, cf: &ColumnFamily, word: &str, def: &Def) {
+    for key in phonetic_keys(word, def) {
+        batch.put_cf(cf, phonetic_key(&key, word), b"");
+    }
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// Headwords that sound like the query, closest spelling first, labelled "q ≈ head".
+    /// Those read before `budget` ran out.
+    pub fn phonetic_matches(&self, query: &str, budget: &Budget) -> Result<Vec<(String, String)>> {
+        let (primary, secondary) = double_metaphone(query);
+        let mut keys = vec![primary, secondary];
+        keys.extend(pinyin_key(query));
+        keys.retain(|k| !k.is_empty());
+        keys.dedup();
+
+        let db = self.db.read().unwrap();
+        let cf = cf_handle(&db, PHONETIC_CF)?;
+        let mut heads: Vec<String> = vec![];
+        let mut seen = HashSet::new();
+        'keys: for key in keys {
+            let mut prefix = key.into_bytes();
+            prefix.push(SEP);
+            for r in db
+                .iterator_cf(cf, IteratorMode::From(&prefix, rocksdb::Direction::Forward))
+                .take(MAX_SCAN)
+            {
+                if budget.exhausted() {
+                    break 'keys;
+                }
+                let (k, _) = r?;
+                if !k.starts_with(&prefix) {
+                    break;
+                }
+                if let Ok(head) = std::str::from_utf8(&k[prefix.len()..]) {
+                    if seen.insert(head.to_owned()) {
+                        heads.push(head.to_owned());
+                    }
+                }
+            }
+        }
+        let q = normalize(query);
+        heads.sort_by_cached_key(|h| {
+            (
+                edit_distance(&q, &normalize(h)),
+                self.freq_penalty(h).to_bits(),
+                h.chars().count(),
+            )
+        });
+        heads.truncate(MAX_PER_KEY);
+        Ok(heads
+            .into_iter()
+            .map(|h| {
+                let label = format!("{} ≈ {}", query, h);
+                (h, label)
+            })
+            .collect())
+    }
+
+    /// Fills the phonetic keys and the IPA index from every record, run by `build`
+    pub fn build_phonetic(&self) -> Result<()> {
+        let db = self.db.read().unwrap();
+        let cf = cf_handle(&db, PHONETIC_CF)?;
+        let ipa = cf_handle(&db, IPA_CF)?;
+        let mut batch = WriteBatch::default();
+        for r in db.iterator(IteratorMode::Start) {
+            let (k, v) = r?;
+            match decode_record(&k, &v) {
+                Ok(rec) => {
+                    put_phonetic(&mut batch, cf, &rec.word, &rec.def);
+                    put_ipa(&mut batch, ipa, &rec.word, &rec.def);
+                }
+                Err(e) => report_damaged(&[e]),
+            }
+            if batch.len() >= IMPORT_BATCH {
+                db.write(std::mem::take(&mut batch))?;
+            }
+        }
+        db.write(batch)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_phonetic() {
+    let dm = |w: &str| double_metaphone(w);
+    assert_eq!(dm("knowledge").0, "NLJ");
+    assert_eq!(dm("nolij").0, "NLJ");
+    assert_eq!(dm("Smith"), ("SM0".to_owned(), "XMT".to_owned()));
+    assert_eq!(dm("laugh").0, "LF");
+    assert_eq!(dm("school").0, "SKL");
+    assert_eq!(dm("character").0, "KRKTR");
+    assert_eq!(dm("phone").0, dm("fone").0);
+
+    assert_eq!(pinyin_key("yǐn sī"), Some("yinsi".to_owned()));
+    assert_eq!(pinyin_key("yin3 si1"), Some("yinsi".to_owned()));
+    assert_eq!(pinyin_key("lǜ"), Some("lu".to_owned()));
+    assert_eq!(pinyin_key("/ˈnɒlɪdʒ/"), None);
+    assert_eq!(pinyin_key("隐私"), None);
+}
@@ -0,0 +1,248 @@
+//! Headwords inside a selected span, longest first, for idioms and phrasal verbs.
+//! "bring more land under cultivation" has "under cultivation" and "bring … under".
+
+use crate::fulltext::is_cjk;
+use crate::normalize::normalize;
+use crate::options::edit_distance;
+use crate::*;
+
+/// Longest run of words tried as one headword
+const MAX_WORDS: usize = 6;
+/// Words allowed between a verb and its particle
+const MAX_GAP: usize = 3;
+/// Headwords sharing a word's letters looked through for a phrase it starts
+const HEAD_SCAN: usize = 500;
+/// Second halves of phrasal verbs, the only ones matched with words in between
+const PARTICLES: &[&str] = &[
+    "about", "across", "after", "along", "apart", "around", "aside", "away", "back", "by", "down",
+    "forward", "in", "into", "off", "on", "out", "over", "round", "through", "together", "up",
+    "under",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PhraseHit {
+    pub word: String,
+    /// Char offsets into the input, `[start, end)`. Two spans for a phrasal verb split by its object.
+    pub spans: Vec<(usize, usize)>,
+    /// Edit distance between the text and the headword, 0 for exact or inflected
+    pub distance: usize,
+    /// Words of the input the headword covers
+    pub words: usize,
+}
+
+impl PhraseHit {
+    fn covered(&self) -> usize {
+        self.spans.iter().map(|(a, b)| b - a).sum()
+    }
+
+    fn within(&self, other: &PhraseHit) -> bool {
+        self.spans
+            .iter()
+            .all(|(a, b)| other.spans.iter().any(|(c, d)| c <= a && b <= d))
+    }
+}
+
+/// Words with their char offsets. Each CJK character is a word of its own.
+pub fn words(text: &str) -> Vec<(usize, usize)> {
+    let mut res = vec![];
+    // Start and the end of its last letter, so "well-known-" ends before the hyphen
+    let mut word: Option<(usize, usize)> = None;
+    for (i, c) in text.chars().enumerate() {
+        if is_cjk(c) {
+            res.extend(word.take());
+            res.push((i, i + 1));
+        } else if c.is_alphanumeric() {
+            word = Some((word.map_or(i, |w| w.0), i + 1));
+        } else if !(word.is_some() && (c == '\'' || c == '’' || c == '-')) {
+            res.extend(word.take());
+        }
+    }
+    res.extend(word);
+    res
+}
+
+/// Exact hits first, then by words covered and chars covered, then position.
+/// Hits inside a longer exact one are dropped, so "bring … under" hides "bring" and "under".
+pub fn rank_hits(mut hits: Vec<PhraseHit>, num: usize) -> Vec<PhraseHit> {
+    hits.sort_by(|a, b| {
+        (
+            a.distance > 0,
+            b.words,
+            b.covered(),
+            a.spans[0].0,
+            a.distance,
+        )
+            .cmp(&(
+                b.distance > 0,
+                a.words,
+                a.covered(),
+                b.spans[0].0,
+                b.distance,
+            ))
+    });
+    let mut res: Vec<PhraseHit> = vec![];
+    for h in hits {
+        if res.len() >= num {
+            break;
+        }
+        let hidden = res
+            .iter()
+            .any(|r| r.distance == 0 && (r.words > h.words || r.word == h.word) && h.within(r));
+        if !hidden {
+            res.push(h);
+        }
+    }
+    res
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// Headword for the words, also with the first one uninflected, "brought under" finds "bring under"
+    fn phrase_head(&self, words: &[&str], lemmas: &[String]) -> Result<Option<String>> {
+        if let Some(head) = self
+            .normalized_matches(&words.join(" "))?
+            .into_iter()
+            .next()
+        {
+            return Ok(Some(head));
+        }
+        for lemma in lemmas {
+            let mut w = vec![lemma.as_str()];
+            w.extend(&words[1..]);
+            if let Some(head) = self.normalized_matches(&w.join(" "))?.into_iter().next() {
+                return Ok(Some(head));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether a headword of several words starts with the word, like "bring under" with "bring".
+    /// CJK words aren't spaced, any longer headword counts.
+    fn starts_phrase(&self, word: &str) -> Result<bool> {
+        let lower = word.to_lowercase();
+        let cjk = word.chars().all(is_cjk);
+        Ok(self.prefixed(word, HEAD_SCAN)?.iter().any(|h| {
+            h.to_lowercase()
+                .strip_prefix(&lower)
+                .is_some_and(|rest| rest.starts_with(' ') || (cjk && !rest.is_empty()))
+        }))
+    }
+
+    /// Every run of up to [MAX_WORDS] words is looked up, and verb-particle pairs with words between.
+    /// Runs of two words or more without a headword go to `fuzzy` when their first word,
+    /// or its lemma, starts a phrase. Hits within a few edits are kept.
+    pub fn phrase_hits(
+        &self,
+        text: &str,
+        opts: &SearchOptions,
+        fuzzy: impl Fn(&str) -> Result<candidates>,
+    ) -> Result<Vec<PhraseHit>> {
+        let opts = &opts.started();
+        let chars: Vec<char> = text.chars().collect();
+        let slice = |a: usize, b: usize| -> String { chars[a..b].iter().collect() };
+        let toks = words(text);
+        let strs: Vec<String> = toks.iter().map(|(a, b)| slice(*a, *b)).collect();
+        let mut hits = vec![];
+        for i in 0..toks.len() {
+            if opts.budget.exhausted() {
+                break;
+            }
+            let mut lemmas = self.harvested_lemmas(&strs[i])?;
+            lemmas.extend(self.lemmatizer.candidates(&strs[i]));
+            // Looked up the first time a run needs it
+            let mut opens: Option<bool> = None;
+            for n in (1..=MAX_WORDS.min(toks.len() - i)).rev() {
+                let span = (toks[i].0, toks[i + n - 1].1);
+                let run: Vec<&str> = strs[i..i + n].iter().map(String::as_str).collect();
+                if let Some(word) = self.phrase_head(&run, &lemmas)? {
+                    hits.push(PhraseHit {
+                        word,
+                        spans: vec![span],
+                        distance: 0,
+                        words: n,
+                    });
+                    continue;
+                }
+                if n < 2 || opts.budget.exhausted() {
+                    continue;
+                }
+                let starts = match opens {
+                    Some(o) => o,
+                    None => {
+                        let mut o = self.starts_phrase(&strs[i])?;
+                        for l in &lemmas {
+                            o = o || self.starts_phrase(l)?;
+                        }
+                        *opens.insert(o)
+                    }
+                };
+                if !starts {
+                    continue;
+                }
+                // As it appears, so CJK runs aren't split by spaces
+                let typed = slice(span.0, span.1);
+                let norm = normalize(&typed);
+                let allowed = (norm.chars().count() / 6).clamp(1, opts.distance.max(1));
+                for c in fuzzy(&typed)? {
+                    let d = edit_distance(&norm, &normalize(&c));
+                    if d > 0 && d <= allowed {
+                        hits.push(PhraseHit {
+                            word: c,
+                            spans: vec![span],
+                            distance: d,
+                            words: n,
+                        });
+                    }
+                }
+            }
+            for j in i + 2..(i + 2 + MAX_GAP).min(toks.len()) {
+                if !PARTICLES.contains(&strs[j].to_lowercase().as_str()) {
+                    continue;
+                }
+                if let Some(word) = self.phrase_head(&[&strs[i], &strs[j]], &lemmas)? {
+                    hits.push(PhraseHit {
+                        word,
+                        spans: vec![toks[i], toks[j]],
+                        distance: 0,
+                        words: 2,
+                    });
+                }
+            }
+        }
+        Ok(rank_hits(hits, opts.num))
+    }
+}
+
+#[test]
+fn test_phrase() {
+    let text = "bring more land under cultivation";
+    assert_eq!(
+        words(text),
+        vec![(0, 5), (6, 10), (11, 15), (16, 21), (22, 33)]
+    );
+    assert_eq!(words("don't 银行卡"), vec![(0, 5), (6, 7), (7, 8), (8, 9)]);
+    assert_eq!(words("-well-known- it’s"), vec![(1, 11), (13, 17)]);
+
+    let hit = |word: &str, spans: Vec<(usize, usize)>, distance| PhraseHit {
+        word: word.to_owned(),
+        words: spans.len().max(word.split(' ').count()),
+        spans,
+        distance,
+    };
+    let ranked = rank_hits(
+        vec![
+            hit("land", vec![(11, 15)], 0),
+            hit("under", vec![(16, 21)], 0),
+            hit("cultivation", vec![(22, 33)], 0),
+            hit("bring under", vec![(0, 5), (16, 21)], 0),
+            hit("under cultivation", vec![(16, 33)], 0),
+            hit("bring", vec![(0, 5)], 0),
+            hit("more or less", vec![(6, 15)], 1),
+        ],
+        10,
+    );
+    let names: Vec<&str> = ranked.iter().map(|h| h.word.as_str()).collect();
+    assert_eq!(
+        names,
+        vec!["under cultivation", "bring under", "land", "more or less"]
+    );
+}
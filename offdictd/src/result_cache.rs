@@ -0,0 +1,108 @@
+//! Recent search results, so a lookup the panel, the API or the REPL repeats skips the index and RocksDB.
+//! Cleared whenever imports, overlay edits or a new index could change them.
+
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Mutex;
+
+use lru::LruCache;
+
+use crate::options::Budget;
+use crate::*;
+
+pub const DEFAULT_RESULT_CACHE: usize = 1024;
+
+/// Results before personal ranking, which changes with every lookup, and where their stages start
+#[derive(Clone)]
+pub struct Cached {
+    pub defs: Vec<DefItemWrapped>,
+    pub exact: usize,
+    pub fuzzy: usize,
+}
+
+pub struct ResultCache {
+    /// None when sized 0
+    lru: Option<Mutex<LruCache<(String, SearchOptions), Cached>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// The query as typed, labels like "running → run" and the spans of matches depend on its spelling.
+/// The deadline and cancel flag are left out, they don't change what was asked.
+fn cache_key(query: &str, opts: &SearchOptions) -> (String, SearchOptions) {
+    (
+        query.to_owned(),
+        SearchOptions {
+            budget: Budget::default(),
+            ..opts.clone()
+        },
+    )
+}
+
+impl ResultCache {
+    pub fn new(size: usize) -> Self {
+        Self {
+            lru: NonZeroUsize::new(size).map(|n| Mutex::new(LruCache::new(n))),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, query: &str, opts: &SearchOptions) -> Option<Cached> {
+        let lru = self.lru.as_ref()?;
+        let found = lru.lock().unwrap().get(&cache_key(query, opts)).cloned();
+        match found {
+            Some(_) => self.hits.fetch_add(1, Relaxed),
+            None => self.misses.fetch_add(1, Relaxed),
+        };
+        found
+    }
+
+    pub fn put(&self, query: &str, opts: &SearchOptions, results: Cached) {
+        if let Some(lru) = &self.lru {
+            lru.lock().unwrap().put(cache_key(query, opts), results);
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Some(lru) = &self.lru {
+            lru.lock().unwrap().clear();
+        }
+    }
+
+    /// Hits and misses since the daemon started
+    pub fn counts(&self) -> (u64, u64) {
+        (self.hits.load(Relaxed), self.misses.load(Relaxed))
+    }
+}
+
+#[test]
+fn test_result_cache() {
+    let cache = ResultCache::new(1);
+    let opts = SearchOptions::top(3);
+    let cached = |word: &str| Cached {
+        defs: vec![DefItemWrapped {
+            word: word.to_owned(),
+            ..Default::default()
+        }],
+        exact: 1,
+        fuzzy: 1,
+    };
+    assert!(cache.get("Apple", &opts).is_none());
+    cache.put("Apple", &opts, cached("apple"));
+    assert!(cache.get("apple", &opts).is_none());
+    assert_eq!(cache.get("Apple", &opts).unwrap().defs[0].word, "apple");
+    assert!(cache.get("apple", &SearchOptions::top(4)).is_none());
+    let started = SearchOptions {
+        budget_ms: Some(10),
+        ..opts.clone()
+    };
+    cache.put("pear", &started, cached("pear"));
+    // Bounded at one
+    assert!(cache.get("Apple", &opts).is_none());
+    assert!(cache.get("pear", &started.started()).is_some());
+    cache.clear();
+    assert!(cache.get("pear", &started).is_none());
+    assert_eq!(cache.counts(), (2, 5));
+    assert!(ResultCache::new(0).get("pear", &opts).is_none());
+}
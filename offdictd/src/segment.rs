@@ -0,0 +1,137 @@
+//! Splits a selection into lookup units: words for alphabetic scripts,
+//! and for CJK, which has no spaces, the longest headwords from the left.
+
+use std::collections::HashSet;
+
+use crate::fulltext::is_cjk;
+use crate::phrase::words;
+use crate::*;
+
+/// Longest CJK headword tried
+const MAX_CJK: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Unit {
+    pub text: String,
+    /// Char offsets into the input, `[start, end)`
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Forward maximum matching over CJK runs, a single character when nothing longer is a word
+pub fn segment(text: &str, is_word: impl Fn(&str) -> bool) -> Vec<Unit> {
+    let chars: Vec<char> = text.chars().collect();
+    let unit = |start: usize, end: usize| Unit {
+        text: chars[start..end].iter().collect(),
+        start,
+        end,
+    };
+    let toks = words(text);
+    let mut res = vec![];
+    let mut i = 0;
+    while i < toks.len() {
+        let (start, end) = toks[i];
+        i += 1;
+        if !is_cjk(chars[start]) {
+            res.push(unit(start, end));
+            continue;
+        }
+        // CJK characters come one per token, adjacent ones make a run
+        let mut run_end = end;
+        while i < toks.len() && toks[i].0 == run_end && is_cjk(chars[run_end]) {
+            run_end = toks[i].1;
+            i += 1;
+        }
+        let mut p = start;
+        while p < run_end {
+            let len = (2..=MAX_CJK.min(run_end - p))
+                .rev()
+                .find(|n| is_word(&chars[p..p + n].iter().collect::<String>()))
+                .unwrap_or(1);
+            res.push(unit(p, p + len));
+            p += len;
+        }
+    }
+    res
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    pub fn segment(&self, text: &str) -> Vec<Unit> {
+        segment(text, |w| {
+            self.normalized_matches(w).is_ok_and(|m| !m.is_empty())
+        })
+    }
+}
+
+impl<Ix: Indexer> Offdict<Ix>
+where
+    Self: Diverge,
+{
+    /// The top result of each distinct unit, in the order they appear, up to `opts.num` units.
+    /// Units without a result are left out.
+    pub fn lookup_units(
+        &self,
+        text: &str,
+        opts: &SearchOptions,
+    ) -> Result<Vec<(Unit, DefItemWrapped)>> {
+        self.lookup_units_of(self.segment(text), opts)
+    }
+
+    /// [Self::lookup_units] of units already segmented
+    pub fn lookup_units_of(
+        &self,
+        units: Vec<Unit>,
+        opts: &SearchOptions,
+    ) -> Result<Vec<(Unit, DefItemWrapped)>> {
+        let opts = &opts.started();
+        let mut seen = HashSet::new();
+        let mut res = vec![];
+        for u in units {
+            if res.len() >= opts.num || opts.budget.exhausted() {
+                break;
+            }
+            if !seen.insert(u.text.to_lowercase()) {
+                continue;
+            }
+            let one = SearchOptions {
+                num: 1,
+                // Short words are a typo away from too many others
+                distance: (u.text.chars().count() > 3) as usize,
+                mode: MatchMode::Word,
+                ..opts.clone()
+            };
+            if let Some(w) = self.search(&u.text, &one)?.value.into_iter().next() {
+                res.push((u, w));
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[test]
+fn test_segment() {
+    let dict = ["银行", "银行卡", "密码", "卡"];
+    let is_word = |w: &str| dict.contains(&w);
+    let units: Vec<String> = segment("Enter 银行卡密码 now, 请", is_word)
+        .into_iter()
+        .map(|u| u.text)
+        .collect();
+    assert_eq!(units, vec!["Enter", "银行卡", "密码", "now", "请"]);
+
+    let units = segment("的银行", is_word);
+    assert_eq!(
+        units,
+        vec![
+            Unit {
+                text: "的".to_owned(),
+                start: 0,
+                end: 1
+            },
+            Unit {
+                text: "银行".to_owned(),
+                start: 1,
+                end: 3
+            },
+        ]
+    );
+}
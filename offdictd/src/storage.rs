@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rocksdb::statistics::Ticker;
+use rocksdb::{
+    properties, BlockBasedOptions, Cache, DBCompressionType, Options, DEFAULT_COLUMN_FAMILY_NAME,
+};
+
+use crate::config::{Compression, StorageConfig};
+use crate::*;
+
+/// RocksDB defaults to 7 levels
+const NUM_LEVELS: usize = 7;
+
+impl From<Compression> for DBCompressionType {
+    fn from(value: Compression) -> Self {
+        match value {
+            Compression::None => Self::None,
+            Compression::Snappy => Self::Snappy,
+            Compression::Lz4 => Self::Lz4,
+            Compression::Zstd => Self::Zstd,
+        }
+    }
+}
+
+/// Applies the `storage` section on top of options that are already set up for the key layout
+pub fn apply_storage(
+    conf: &StorageConfig,
+    opts: &mut Options,
+    table: &mut BlockBasedOptions,
+) -> Option<Cache> {
+    let cache =
+        (conf.block_cache_mb > 0).then(|| Cache::new_lru_cache(conf.block_cache_mb * 1024 * 1024));
+    tune_storage(conf, opts, table, cache.as_ref());
+    cache
+}
+
+/// The same, sharing a block cache made by [apply_storage]
+pub fn tune_storage(
+    conf: &StorageConfig,
+    opts: &mut Options,
+    table: &mut BlockBasedOptions,
+    cache: Option<&Cache>,
+) {
+    if let Some(last) = conf.compression.last() {
+        let levels: Vec<DBCompressionType> = (0..NUM_LEVELS)
+            .map(|l| (*conf.compression.get(l).unwrap_or(last)).into())
+            .collect();
+        opts.set_compression_per_level(&levels);
+    }
+    if let Some(bottom) = conf.bottommost_compression {
+        opts.set_bottommost_compression_type(bottom.into());
+    }
+    if conf.bloom_bits > 0. {
+        table.set_bloom_filter(conf.bloom_bits, false);
+    }
+    if conf.bulk_load {
+        opts.prepare_for_bulk_load();
+    }
+    opts.enable_statistics();
+
+    match cache {
+        Some(cache) => table.set_block_cache(cache),
+        None => table.disable_cache(),
+    }
+}
+
+#[derive(Default)]
+pub struct LevelUsage {
+    pub files: usize,
+    pub bytes: u64,
+    pub entries: u64,
+}
+
+#[derive(Default)]
+pub struct DictUsage {
+    pub records: usize,
+    /// Uncompressed key and value bytes
+    pub bytes: u64,
+}
+
+pub struct StorageReport {
+    /// Per column family, the records are in [DEFAULT_COLUMN_FAMILY_NAME]
+    pub tables: BTreeMap<String, u64>,
+    pub levels: BTreeMap<i32, LevelUsage>,
+    pub dicts: BTreeMap<String, DictUsage>,
+    /// Counted since the database was opened, by the process making the report.
+    /// A report from the CLI only counts its own reads, the daemon's are in `/stat`.
+    pub cache_hit: u64,
+    pub cache_miss: u64,
+    pub cache_usage: Option<usize>,
+}
+
+impl StorageReport {
+    pub fn sst_bytes(&self) -> u64 {
+        self.tables.values().sum()
+    }
+    pub fn raw_bytes(&self) -> u64 {
+        self.dicts.values().map(|d| d.bytes).sum()
+    }
+    /// Of the records alone, the derived tables have no uncompressed size to compare with
+    pub fn compression_ratio(&self) -> f64 {
+        let records = self
+            .tables
+            .get(DEFAULT_COLUMN_FAMILY_NAME)
+            .copied()
+            .unwrap_or_default();
+        self.raw_bytes() as f64 / records.max(1) as f64
+    }
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let total = self.cache_hit + self.cache_miss;
+        (total > 0).then(|| self.cache_hit as f64 / total as f64)
+    }
+}
+
+fn mib(b: u64) -> f64 {
+    b as f64 / (1024. * 1024.)
+}
+
+impl fmt::Display for StorageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "SST files, {:.1} MiB", mib(self.sst_bytes()))?;
+        for (name, bytes) in &self.tables {
+            writeln!(f, "  {}: {:.1} MiB", name, mib(*bytes))?;
+        }
+        for (level, u) in &self.levels {
+            writeln!(
+                f,
+                "  L{}: {} files, {:.1} MiB, {} entries",
+                level,
+                u.files,
+                mib(u.bytes),
+                u.entries
+            )?;
+        }
+        writeln!(
+            f,
+            "Records uncompressed, {:.1} MiB. Compression ratio, {:.2}",
+            mib(self.raw_bytes()),
+            self.compression_ratio()
+        )?;
+        match self.cache_hit_rate() {
+            Some(rate) => write!(
+                f,
+                "Block cache of this process, {} hits, {} misses, {:.1}% hit rate",
+                self.cache_hit,
+                self.cache_miss,
+                rate * 100.
+            )?,
+            None => write!(f, "Block cache of this process, no lookups yet")?,
+        }
+        if let Some(usage) = self.cache_usage {
+            write!(f, ", {:.1} MiB in use", mib(usage as u64))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Per dictionary:")?;
+        for (name, u) in &self.dicts {
+            writeln!(
+                f,
+                "  {}: {} records, {:.1} MiB",
+                name,
+                u.records,
+                mib(u.bytes)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// Walks every record to attribute bytes to dictionaries, so it takes as long as `stat`
+    pub fn storage_report(&self) -> Result<StorageReport> {
+        let db = self.db.read().unwrap();
+        let mut tables = BTreeMap::new();
+        tables.insert(
+            DEFAULT_COLUMN_FAMILY_NAME.to_owned(),
+            db.property_int_value(properties::TOTAL_SST_FILES_SIZE)?
+                .unwrap_or_default(),
+        );
+        for name in AUX_CFS {
+            let bytes = db
+                .property_int_value_cf(cf_handle(&db, name)?, properties::TOTAL_SST_FILES_SIZE)?
+                .unwrap_or_default();
+            tables.insert(name.to_string(), bytes);
+        }
+        let mut levels: BTreeMap<i32, LevelUsage> = BTreeMap::new();
+        for file in db.live_files()? {
+            let u = levels.entry(file.level).or_default();
+            u.files += 1;
+            u.bytes += file.size as u64;
+            u.entries += file.num_entries;
+        }
+        let mut dicts: BTreeMap<String, DictUsage> = BTreeMap::new();
+        for r in db.iterator(rocksdb::IteratorMode::Start) {
+            if let Ok((k, v)) = r {
+                let name = match decode_key(&k) {
+                    Ok((_, dict)) => dict,
+                    Err(_) => "<damaged>".to_owned(),
+                };
+                let u = dicts.entry(name).or_default();
+                u.records += 1;
+                u.bytes += (k.len() + v.len()) as u64;
+            }
+        }
+
+        Ok(StorageReport {
+            tables,
+            levels,
+            dicts,
+            cache_hit: self.db_opts.get_ticker_count(Ticker::BlockCacheHit),
+            cache_miss: self.db_opts.get_ticker_count(Ticker::BlockCacheMiss),
+            cache_usage: self.block_cache.as_ref().map(|c| c.get_usage()),
+        })
+    }
+}
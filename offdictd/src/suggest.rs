@@ -0,0 +1,234 @@
+//! "Did you mean" for queries nothing matches well: words run together or split apart,
+//! and the usual typos, swapped, doubled, extra or missing letters and keyboard neighbours.
+
+use std::collections::HashSet;
+
+use crate::fulltext::is_cjk;
+use crate::*;
+
+pub const DEFAULT_CONFIDENT_SCORE: f32 = 2.;
+/// Suggestions given at most
+const MAX_SUGGESTIONS: usize = 5;
+/// A fix of the spelling, also of where words start
+const TYPO_COST: f32 = 1.;
+/// Splitting into two headwords, more than a typo, so "untill" is "until" before "un till"
+const SPLIT_COST: f32 = 1.5;
+const PHONETIC_COST: f32 = 1.5;
+/// Longer queries only get word boundaries fixed, a typo each way would be too many lookups
+const MAX_TYPO_CHARS: usize = 24;
+const KEYBOARD: [&str; 3] = ["qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// Keys around `c` on a QWERTY keyboard
+fn neighbours(c: char) -> Vec<char> {
+    let Some((row, col)) = KEYBOARD
+        .iter()
+        .enumerate()
+        .find_map(|(r, keys)| keys.find(c).map(|i| (r, i)))
+    else {
+        return vec![];
+    };
+    let mut res = vec![];
+    for keys in &KEYBOARD[row.saturating_sub(1)..=(row + 1).min(KEYBOARD.len() - 1)] {
+        let keys: Vec<char> = keys.chars().collect();
+        for k in keys
+            .iter()
+            .skip(col.saturating_sub(1))
+            .take(3 - (col == 0) as usize)
+        {
+            if *k != c {
+                res.push(*k);
+            }
+        }
+    }
+    res
+}
+
+/// Spellings a typo away from `query`, lowercased, with the kind of typo, likelier kinds first
+pub fn typos(query: &str) -> Vec<(String, &'static str)> {
+    let c: Vec<char> = query.to_lowercase().chars().collect();
+    let mut res = vec![];
+    let mut push = |v: Vec<char>, kind| res.push((v.into_iter().collect::<String>(), kind));
+    for i in 1..c.len() {
+        if c[i - 1] != c[i] {
+            let mut v = c.clone();
+            v.swap(i - 1, i);
+            push(v, "transpose");
+        }
+    }
+    for i in 0..c.len() {
+        let mut v = c.clone();
+        // "occured" for "occurred", and "untill" for "until"
+        if i + 1 < c.len() && c[i] == c[i + 1] {
+            v.remove(i);
+        } else if c[i].is_alphabetic() {
+            v.insert(i, c[i]);
+        }
+        push(v, "double");
+    }
+    for i in 0..c.len() {
+        for k in neighbours(c[i]) {
+            let mut v = c.clone();
+            v[i] = k;
+            push(v, "keyboard");
+        }
+    }
+    for i in 0..c.len() {
+        let mut v = c.clone();
+        v.remove(i);
+        push(v, "extra");
+    }
+    // Letters of other scripts are too many to try
+    if c.iter().all(char::is_ascii) {
+        for i in 0..=c.len() {
+            for k in 'a'..='z' {
+                let mut v = c.clone();
+                v.insert(i, k);
+                push(v, "missing");
+            }
+        }
+    }
+    let query: String = c.into_iter().collect();
+    let mut seen = HashSet::new();
+    res.retain(|(s, _)| !s.is_empty() && *s != query && seen.insert(s.clone()));
+    res
+}
+
+/// Where the query splits in two, and the query without its spaces and hyphens
+pub fn rebound(query: &str) -> (Vec<(String, String)>, Option<String>) {
+    let c: Vec<char> = query.trim().chars().collect();
+    let splits = (1..c.len())
+        .filter(|&i| {
+            [c[i - 1], c[i]]
+                .iter()
+                .all(|c| c.is_alphabetic() && !is_cjk(*c))
+        })
+        .map(|i| (c[..i].iter().collect(), c[i..].iter().collect()))
+        .collect();
+    let joined: String = c.iter().filter(|c| !matches!(c, ' ' | '-')).collect();
+    let joined = Some(joined).filter(|j| j.chars().count() < c.len());
+    (splits, joined)
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    /// Anything found by spelling, case, normalization, inflection or prefix is.
+    /// Fuzzy and phonetic results need a [Match] score within `confident_score`, one more per 8 chars of the query.
+    pub fn confident(&self, query: &str, results: &[DefItemWrapped]) -> bool {
+        let allowed = self.confident_score + (query.chars().count() / 8) as f32;
+        results.iter().any(|w| {
+            w.matched
+                .as_ref()
+                .map_or(true, |m| !m.is_approximate() || m.score.0 <= allowed)
+        })
+    }
+
+    fn headword(&self, spelling: &str) -> Result<Option<String>> {
+        Ok(self.normalized_matches(spelling)?.into_iter().next())
+    }
+
+    /// Headwords the query could be meant as, best first, see [suggest]
+    pub fn suggestions(
+        &self,
+        query: &str,
+        opts: &SearchOptions,
+        penalty: impl Fn(&str) -> f32,
+    ) -> Result<Vec<String>> {
+        suggest(
+            query,
+            opts,
+            |s| self.headword(s),
+            |budget| self.phonetic_matches(query, budget),
+            penalty,
+        )
+    }
+}
+
+/// Each fix counts [TYPO_COST], a split into two headwords [SPLIT_COST] and a phonetic match
+/// [PHONETIC_COST]. `penalty` adds to it for rarer words, for both halves of a split.
+pub fn suggest(
+    query: &str,
+    opts: &SearchOptions,
+    headword: impl Fn(&str) -> Result<Option<String>>,
+    phonetic: impl FnOnce(&Budget) -> Result<Vec<(String, String)>>,
+    penalty: impl Fn(&str) -> f32,
+) -> Result<Vec<String>> {
+    let opts = &opts.started();
+    let mut found: Vec<(f32, String)> = vec![];
+    let mut add = |cost: f32, word: String| match found.iter_mut().find(|f| f.1 == word) {
+        Some(f) => f.0 = f.0.min(cost),
+        None => found.push((cost, word)),
+    };
+    let (splits, joined) = rebound(query);
+    if let Some(w) = joined.map(|j| headword(&j)).transpose()?.flatten() {
+        add(TYPO_COST + penalty(&w), w);
+    }
+    for (l, r) in splits {
+        if opts.budget.exhausted() {
+            break;
+        }
+        if let Some(w) = headword(&format!("{} {}", l, r))? {
+            add(TYPO_COST + penalty(&w), w);
+        } else if let (Some(l), Some(r)) = (headword(&l)?, headword(&r)?) {
+            add(
+                SPLIT_COST + penalty(&l) + penalty(&r),
+                format!("{} {}", l, r),
+            );
+        }
+    }
+    if query.chars().count() <= MAX_TYPO_CHARS {
+        for (t, _) in typos(query) {
+            if opts.budget.exhausted() {
+                break;
+            }
+            if let Some(w) = headword(&t)? {
+                add(TYPO_COST + penalty(&w), w);
+            }
+        }
+    }
+    if !opts.budget.exhausted() {
+        for (w, _) in phonetic(&opts.budget)? {
+            add(PHONETIC_COST + penalty(&w), w);
+        }
+    }
+    found.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(found
+        .into_iter()
+        .map(|f| f.1)
+        .take(MAX_SUGGESTIONS.min(opts.num.max(1)))
+        .collect())
+}
+
+#[test]
+fn test_suggest() {
+    assert_eq!(neighbours('q'), vec!['w', 'a', 's']);
+    assert_eq!(neighbours('g').len(), 8);
+    let t = typos("teh");
+    assert_eq!(t[0], ("eth".to_owned(), "transpose"));
+    assert!(t.contains(&("the".to_owned(), "transpose")));
+    assert!(typos("untill").contains(&("until".to_owned(), "double")));
+    assert!(typos("recieve").iter().any(|t| t.0 == "receive"));
+    assert!(typos("acomodate").iter().any(|t| t.0 == "accomodate"));
+    assert!(!typos("ab").iter().any(|t| t.0 == "ab"));
+
+    let (splits, joined) = rebound("inthe");
+    assert_eq!(splits[1], ("in".to_owned(), "the".to_owned()));
+    assert_eq!(joined, None);
+    assert_eq!(rebound("run way").1, Some("runway".to_owned()));
+    assert!(rebound("银行卡").0.is_empty());
+
+    // Log-scaled penalties of a frequency list, the halves are common words too
+    let heads = ["until", "un", "till"];
+    let headword = |s: &str| Ok(heads.contains(&s).then(|| s.to_owned()));
+    let found = suggest(
+        "untill",
+        &SearchOptions::default(),
+        headword,
+        |_| Ok(vec![]),
+        |w| match w {
+            "until" => 0.3,
+            "till" => 0.4,
+            _ => 0.5,
+        },
+    )
+    .unwrap();
+    assert_eq!(found, vec!["until", "un till"]);
+}
@@ -6,11 +6,16 @@ use std::cmp;
 use std::collections::BTreeMap;
 use std::error::Error;
 
-pub const FIXTURE_PATH: &'static str = "./offdictd/fixtures/dict.yaml";
+pub const FIXTURE_PATH: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/dict.yaml");
 
+use crate::config::StorageConfig;
 use crate::def::SrcDef;
 use crate::def_bin::{Def, WrapperDef};
-use crate::{def, def_bin, DefItemWrapped};
+use crate::index::Index;
+use crate::{
+    def, def_bin, DefItemWrapped, Diverge, Offdict, OverlayEntry, SearchOptions, ALL_DICTS,
+    USER_DICT,
+};
 use bincode::Options;
 // use postcard;
 use std::fs::File;
@@ -51,6 +56,24 @@ pub fn collect_defs(defs: Vec<Def>) -> BTreeMap<String, DefItemWrapped> {
     map
 }
 
+/// An empty database in a fresh directory under the system's temp dir, without an index
+pub fn temp_db(name: &str) -> Result<Offdict<Index>> {
+    let dir = std::env::temp_dir().join(format!("offdictd-{}-{}", name, std::process::id()));
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    Offdict::open_db_with(dir, &StorageConfig::default())
+}
+
+pub fn test_entry(word: &str, dict: &str, en: &str) -> Def {
+    Def {
+        word: Some(word.to_owned()),
+        dictName: Some(dict.to_owned()),
+        EN: Some(en.to_owned()),
+        ..Default::default()
+    }
+}
+
 fn test_bincode<T: for<'a> Deserialize<'a> + Serialize + Debug + PartialEq>(value: T) {
     let record_coder = bincode::DefaultOptions::new()
         .with_varint_encoding()
@@ -182,3 +205,139 @@ fn from_yaml() -> Result<(), Box<dyn Error>> {
 
 //     Ok(())
 // }
+
+#[test]
+fn overlay_merge() -> Result<()> {
+    let db = temp_db("overlay")?;
+    db.import_defs(vec![
+        test_entry("apple", "a", "a fruit"),
+        test_entry("apple", "b", "a company"),
+        test_entry("pear", "a", "a fruit"),
+    ])?;
+    db.overlay_put(
+        "apple",
+        "a",
+        OverlayEntry::Edit(test_entry("apple", "a", "a red fruit")),
+    )?;
+    db.overlay_put("apple", "b", OverlayEntry::Hidden)?;
+    db.overlay_put(
+        "apple",
+        USER_DICT,
+        OverlayEntry::Edit(test_entry("apple", USER_DICT, "mine")),
+    )?;
+    db.overlay_put("pear", ALL_DICTS, OverlayEntry::Hidden)?;
+
+    let en = |word: &str| -> Vec<(String, Option<String>)> {
+        let w = db.retrieve(word.to_owned()).value.unwrap_or_default();
+        w.items.into_iter().map(|(d, def)| (d, def.EN)).collect()
+    };
+    assert_eq!(
+        en("apple"),
+        vec![
+            ("a".to_owned(), Some("a red fruit".to_owned())),
+            (USER_DICT.to_owned(), Some("mine".to_owned())),
+        ]
+    );
+    assert!(en("pear").is_empty());
+
+    // The imported definitions were left alone
+    db.overlay_remove("apple", "a")?;
+    db.overlay_remove("apple", "b")?;
+    db.overlay_remove("pear", ALL_DICTS)?;
+    assert_eq!(en("apple").len(), 3);
+    assert_eq!(
+        en("pear"),
+        vec![("a".to_owned(), Some("a fruit".to_owned()))]
+    );
+    Ok(())
+}
+
+#[test]
+fn personal_stages() -> Result<()> {
+    let mut db = temp_db("stages")?;
+    db.personalize = true;
+    db.import_defs(vec![
+        test_entry("went", "a", "past tense of go"),
+        test_entry("go", "a", "to move"),
+    ])?;
+    for _ in 0..5 {
+        db.record_lookup("go", &["a".to_owned()])?;
+    }
+    let words =
+        |res: &[DefItemWrapped]| -> Vec<String> { res.iter().map(|w| w.word.clone()).collect() };
+
+    // Looked up often, an inflection still comes after the exact match
+    let found = db.search("went", &SearchOptions::top(5))?;
+    assert_eq!(words(&found.value), vec!["went", "go"]);
+
+    // Within a stage the history decides
+    let mut res: Vec<DefItemWrapped> = ["went", "gone", "go"]
+        .into_iter()
+        .map(|w| DefItemWrapped {
+            word: w.to_owned(),
+            ..Default::default()
+        })
+        .collect();
+    db.personal_stages(&mut res, 1, 1)?;
+    assert_eq!(words(&res), vec!["went", "go", "gone"]);
+    Ok(())
+}
+
+#[test]
+fn search_budget() -> Result<()> {
+    let db = temp_db("budget")?;
+    db.import_defs(vec![
+        test_entry("running", "a", "the act of moving fast"),
+        test_entry("run", "a", "to move fast"),
+    ])?;
+    let words =
+        |res: &[DefItemWrapped]| -> Vec<String> { res.iter().map(|w| w.word.clone()).collect() };
+
+    // Exact matches whatever the budget, the rest is cut and not cached
+    let cancelled = SearchOptions::top(5);
+    cancelled.budget.cancel();
+    let found = db.search("running", &cancelled)?;
+    assert!(found.partial);
+    assert_eq!(words(&found.value), vec!["running"]);
+    assert_eq!(db.results.counts(), (0, 1));
+
+    let found = db.search("running", &SearchOptions::top(5))?;
+    assert!(!found.partial);
+    assert_eq!(words(&found.value), vec!["running", "run"]);
+    assert_eq!(db.results.counts(), (0, 2));
+
+    let timed = SearchOptions {
+        budget_ms: Some(0),
+        ..SearchOptions::top(5)
+    };
+    assert!(db.search("running", &timed)?.partial);
+    assert!(db.fulltext("fast", &cancelled)?.partial);
+    assert!(!db.fulltext("fast", &SearchOptions::top(5))?.partial);
+    Ok(())
+}
+
+#[test]
+fn result_cache_invalidation() -> Result<()> {
+    let db = temp_db("cache")?;
+    db.import_defs(vec![test_entry("apple", "a", "a fruit")])?;
+    let dicts = || -> Result<Vec<String>> {
+        let found = db.search("apple", &SearchOptions::top(5))?;
+        Ok(found
+            .value
+            .into_iter()
+            .flat_map(|w| w.items.into_keys())
+            .collect())
+    };
+    assert_eq!(dicts()?, vec!["a"]);
+    assert_eq!(dicts()?, vec!["a"]);
+    assert_eq!(db.results.counts(), (1, 1));
+
+    // Imports and overlay edits show up at once
+    db.import_defs(vec![test_entry("apple", "b", "a company")])?;
+    assert_eq!(dicts()?, vec!["a", "b"]);
+    assert_eq!(db.results.counts(), (1, 2));
+    db.overlay_put("apple", "b", OverlayEntry::Hidden)?;
+    assert_eq!(dicts()?, vec!["a"]);
+    assert_eq!(db.results.counts(), (1, 3));
+    Ok(())
+}
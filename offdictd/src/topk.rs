@@ -5,22 +5,23 @@ use metacomplete::prefix::meta::Cache;
 use metacomplete::{MetaAutocompleter, TreeStringT};
 use yoke::{Yoke, Yokeable};
 
+use crate::options::edit_distance;
 use crate::*;
 use crate::{candidates, Indexer};
 
+/// Idle caches kept for reuse, more are made while many queries run at once
+const MAX_CACHES: usize = 16;
+
 pub struct Strprox {
     pub yoke: Yoke<MetaAutocompleter<'static>, Mmap>,
-    pub cache: Mutex<Cache<'static>>,
-}
-
-#[derive(new)]
-pub struct TopkParam {
-    num: usize,
+    /// Idle caches. A query takes the one returned last, so a single client typing keeps its own,
+    /// and the lock is only held to take and return one.
+    pub caches: Mutex<Vec<Cache<'static>>>,
 }
 
 impl Indexer for Strprox {
     const FILE_NAME: &'static str = "strprox";
-    type Param = TopkParam;
+    type Param = SearchOptions;
     fn build_all(words: impl IntoIterator<Item = String>, pp: &std::path::Path) -> Result<()> {
         let arr: Vec<_> = words
             .into_iter()
@@ -38,21 +39,58 @@ impl Indexer for Strprox {
             yoke: Yoke::try_attach_to_cart(unsafe { Mmap::map(&f) }?, |data| {
                 bincode::deserialize(data)
             })?,
-            cache: Default::default(),
+            caches: Default::default(),
         };
         println!("index loaded");
         Ok(sel)
     }
-    fn query(&self, query: &str, param: TopkParam) -> Result<crate::candidates> {
-        let mut lk = self.cache.lock().unwrap();
+    fn query(&self, query: &str, opts: &SearchOptions) -> Result<crate::candidates> {
+        let mut cache = self.caches.lock().unwrap().pop().unwrap_or_default();
         let topk = self.yoke.get();
-        let num = 10;
-        let mut rx = topk.threshold_top_k(query, num, 3, &mut lk);
-        rx.truncate(num);
-        let cands: Vec<_> = rx.into_iter().map(|k| k.string).collect();
+        // Whole word mode throws away most prefix matches, so ask for more,
+        // a walk at a time so the budget is checked in between
+        let max_k = match opts.mode {
+            MatchMode::Prefix => opts.num,
+            MatchMode::Word => opts.num * 4,
+        };
+        let mut k = opts.num;
+        let mut cands = vec![];
+        while !opts.budget.exhausted() {
+            let rx = topk.threshold_top_k(query, k, opts.distance, &mut cache);
+            let found = rx.len();
+            cands.clear();
+            for s in rx
+                .into_iter()
+                .filter(|c| c.prefix_distance <= opts.distance)
+                .map(|c| c.string)
+            {
+                if cands.len() >= opts.num || opts.budget.exhausted() {
+                    break;
+                }
+                if opts.mode == MatchMode::Prefix || edit_distance(query, &s) <= opts.distance {
+                    cands.push(s);
+                }
+            }
+            if cands.len() >= opts.num || found < k || k >= max_k {
+                break;
+            }
+            k = (k * 2).min(max_k);
+        }
+        let mut idle = self.caches.lock().unwrap();
+        if idle.len() < MAX_CACHES {
+            idle.push(cache);
+        }
+        drop(idle);
         Ok(cands)
     }
     fn count(&self) -> usize {
         self.yoke.get().len()
     }
+    fn for_each_word(&self, f: &mut dyn FnMut(&str) -> bool) {
+        for s in self.yoke.get().trie.strings.iter() {
+            if !f(&s.to_string()) {
+                return;
+            }
+        }
+    }
 }
@@ -0,0 +1,360 @@
+//! Cross references between entries, like `related: ["(←→ public)"]` or "see colour",
+//! parsed at import so they can be followed both ways
+
+use lazy_regex::regex;
+use rocksdb::{ColumnFamily, IteratorMode, WriteBatch};
+
+use crate::def_bin::{Def, WrapperDef};
+use crate::normalize::normalize;
+use crate::*;
+
+/// Column family of links, stored once from each end
+pub const LINKS_CF: &str = "links";
+const SEP: u8 = 0;
+const OUTGOING: u8 = b'>';
+const INCOMING: u8 = b'<';
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum LinkKind {
+    /// "see colour", "→ colour"
+    See = 0,
+    /// Another spelling or form
+    Variant = 1,
+    /// "= colour"
+    Synonym = 2,
+    /// "←→ public"
+    Antonym = 3,
+    /// Listed under `related` without saying how
+    Related = 4,
+}
+
+impl LinkKind {
+    fn from_u8(b: u8) -> Option<Self> {
+        Some(match b {
+            0 => Self::See,
+            1 => Self::Variant,
+            2 => Self::Synonym,
+            3 => Self::Antonym,
+            4 => Self::Related,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub target: String,
+    pub kind: LinkKind,
+}
+
+/// One end of a stored link, seen from the other end
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct XRef {
+    pub word: String,
+    pub kind: LinkKind,
+    pub dict: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Links {
+    pub word: String,
+    /// Entries this word points to
+    pub outgoing: Vec<XRef>,
+    /// Entries pointing to this word
+    pub incoming: Vec<XRef>,
+}
+
+/// Drops homograph numbers and trailing punctuation. Long phrases are prose, not headwords.
+fn clean_target(t: &str) -> Option<String> {
+    let t = t
+        .trim()
+        .trim_end_matches(|c: char| c.is_ascii_digit() || ".,;:!?)）]】\"”'’".contains(c))
+        .trim_start_matches(|c: char| "\"“'‘".contains(c))
+        .trim();
+    if t.is_empty() || t.chars().count() > 40 || t.split_whitespace().count() > 4 {
+        None
+    } else {
+        Some(t.to_owned())
+    }
+}
+
+/// Text that is nothing but a pointer to another entry
+pub fn parse_reference(text: &str) -> Option<Link> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix(['(', '（', '['])
+        .and_then(|t| t.strip_suffix([')', '）', ']']))
+        .unwrap_or(text)
+        .trim();
+    // Symbols may touch the target, words need a space or colon after them
+    let rules = [
+        (
+            regex!(
+                r"^(?i)(?:(?:←→|<->|↔)\s*|(?:opposite|antonym|反义词?)(?:\s*[:：]\s*|\s+))(.+)$"
+            ),
+            LinkKind::Antonym,
+        ),
+        (
+            regex!(r"^(?i)(?:=\s*|(?:synonym|同义词?)(?:\s*[:：]\s*|\s+))(.+)$"),
+            LinkKind::Synonym,
+        ),
+        (
+            regex!(
+                r"^(?i)(?:(?:→|->)\s*|(?:see(?: also)?|cf\.?|见|参见|参看)(?:\s*[:：]\s*|\s+))(.+)$"
+            ),
+            LinkKind::See,
+        ),
+        (
+            regex!(
+                r"^(?i)(?:(?:an? )?(?:variant|(?:another |alternative |american |british )?spelling|(?:another |other )?form) of|also|亦作|又作|同)(?:\s*[:：]\s*|\s+)(.+)$"
+            ),
+            LinkKind::Variant,
+        ),
+    ];
+    for (re, kind) in rules {
+        if let Some(c) = re.captures(text) {
+            return clean_target(&c[1]).map(|target| Link { target, kind });
+        }
+    }
+    None
+}
+
+/// Pointers inside running text: "(see colour)", "(cf. hue)", or "See colour." opening a sentence.
+/// "to see something clearly" is prose, not a pointer. Single words only, as the end is unclear.
+pub fn find_references(text: &str) -> Vec<Link> {
+    let bracketed =
+        regex!(r"[(（]\s*(?i:see(?: also)?|cf\.?)\s+([\p{L}][\p{L}'-]*)\d*\s*\.?\s*[)）]");
+    let opening = regex!(r"(?:^|[.!?;:]\s+)(?:See(?: also)?|Cf\.)\s+([\p{L}][\p{L}'-]*)");
+    bracketed
+        .captures_iter(text)
+        .chain(opening.captures_iter(text))
+        .filter_map(|c| clean_target(&c[1]))
+        .map(|target| Link {
+            target,
+            kind: LinkKind::See,
+        })
+        .collect()
+}
+
+fn push_link(links: &mut Vec<Link>, link: Link) {
+    if !links.contains(&link) {
+        links.push(link);
+    }
+}
+
+pub fn harvest_links(def: &Def, into: &mut Vec<Link>) {
+    for r in def.related.iter().flatten() {
+        match parse_reference(r) {
+            Some(link) => push_link(into, link),
+            None => {
+                let bare = r.trim().trim_matches(['(', ')', '（', '）']);
+                if let Some(target) = clean_target(bare) {
+                    push_link(
+                        into,
+                        Link {
+                            target,
+                            kind: LinkKind::Related,
+                        },
+                    );
+                }
+            }
+        }
+    }
+    for text in [&def.EN, &def.CN, &def.info].into_iter().flatten() {
+        match parse_reference(text) {
+            Some(link) => push_link(into, link),
+            None => {
+                for link in find_references(text) {
+                    push_link(into, link)
+                }
+            }
+        }
+    }
+    for d in def.definitions.iter().chain(def.groups.iter()).flatten() {
+        harvest_links(d, into);
+    }
+}
+
+fn only_links(def: &Def) -> bool {
+    def.examples.is_none()
+        && [&def.EN, &def.CN, &def.info]
+            .into_iter()
+            .flatten()
+            .all(|t| parse_reference(t).is_some())
+        && def
+            .definitions
+            .iter()
+            .chain(def.groups.iter())
+            .flatten()
+            .all(only_links)
+}
+
+/// An entry that says nothing except where to look instead
+pub fn is_redirect(def: &Def) -> bool {
+    if !only_links(def) {
+        return false;
+    }
+    let mut links = vec![];
+    harvest_links(def, &mut links);
+    !links.is_empty()
+}
+
+fn link_key(this: &str, dir: u8, other: &str, kind: LinkKind) -> Vec<u8> {
+    let mut k = Vec::with_capacity(this.len() + other.len() + 4);
+    k.extend(this.as_bytes());
+    k.extend([SEP, dir, SEP]);
+    k.extend(other.as_bytes());
+    k.extend([SEP, kind as u8]);
+    k
+}
+
+pub fn put_links(batch: &mut WriteBatch, cf: &ColumnFamily, word: &str, dict: &str, def: &Def) {
+    let mut links = vec![];
+    harvest_links(def, &mut links);
+    for link in links {
+        if normalize(&link.target) == normalize(word) {
+            continue;
+        }
+        let out = link_key(&normalize(word), OUTGOING, &link.target, link.kind);
+        let inc = link_key(&normalize(&link.target), INCOMING, word, link.kind);
+        batch.put_cf(cf, out, dict);
+        batch.put_cf(cf, inc, dict);
+    }
+}
+
+impl<Ix: Indexer> Offdict<Ix> {
+    fn links_in(&self, word: &str, dir: u8) -> Result<Vec<XRef>> {
+        let db = self.db.read().unwrap();
+        let cf = cf_handle(&db, LINKS_CF)?;
+        let mut prefix = normalize(word).into_bytes();
+        prefix.extend([SEP, dir, SEP]);
+        let mut res = vec![];
+        for r in db.iterator_cf(cf, IteratorMode::From(&prefix, rocksdb::Direction::Forward)) {
+            let (k, v) = r?;
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            let rest = &k[prefix.len()..];
+            if rest.len() < 2 || rest[rest.len() - 2] != SEP {
+                continue;
+            }
+            let (other, kind) = (&rest[..rest.len() - 2], rest[rest.len() - 1]);
+            if let (Ok(other), Some(kind), Ok(dict)) = (
+                std::str::from_utf8(other),
+                LinkKind::from_u8(kind),
+                std::str::from_utf8(&v),
+            ) {
+                res.push(XRef {
+                    word: other.to_owned(),
+                    kind,
+                    dict: dict.to_owned(),
+                });
+            }
+        }
+        Ok(res)
+    }
+
+    /// Links from and to a headword, in any spelling that normalizes the same
+    pub fn links(&self, word: &str) -> Result<Links> {
+        Ok(Links {
+            word: word.to_owned(),
+            outgoing: self.links_in(word, OUTGOING)?,
+            incoming: self.links_in(word, INCOMING)?,
+        })
+    }
+
+    /// Puts the target's definition under entries that only point elsewhere. One hop, antonyms excluded.
+    pub fn inline_redirects(&self, w: &mut WrapperDef) {
+        for (dict, def) in w.items.iter_mut() {
+            if !is_redirect(def) {
+                continue;
+            }
+            let mut links = vec![];
+            harvest_links(def, &mut links);
+            let Some(link) = links.into_iter().find(|l| l.kind != LinkKind::Antonym) else {
+                continue;
+            };
+            let head = match self.normalized_matches(&link.target) {
+                Ok(m) => m.into_iter().next(),
+                Err(_) => None,
+            };
+            let Some(head) = head.filter(|h| *h != w.word) else {
+                continue;
+            };
+            let r = self.retrieve(head);
+            report_damaged(&r.errors);
+            if let Some(target) = r.value {
+                // Prefer the same dictionary
+                if let Some(t) = target.items.get(dict).or(target.items.values().next()) {
+                    def.definitions.get_or_insert_with(Vec::new).push(t.clone());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_references() {
+    let link = |t: &str, kind| {
+        Some(Link {
+            target: t.to_owned(),
+            kind,
+        })
+    };
+    assert_eq!(
+        parse_reference("(←→ public)"),
+        link("public", LinkKind::Antonym)
+    );
+    assert_eq!(parse_reference("see colour"), link("colour", LinkKind::See));
+    assert_eq!(
+        parse_reference("See colour1."),
+        link("colour", LinkKind::See)
+    );
+    assert_eq!(
+        parse_reference("= colour"),
+        link("colour", LinkKind::Synonym)
+    );
+    assert_eq!(
+        parse_reference("another spelling of colour"),
+        link("colour", LinkKind::Variant)
+    );
+    assert_eq!(parse_reference("见 颜色"), link("颜色", LinkKind::See));
+    assert_eq!(parse_reference("the quality of an object"), None);
+    assert_eq!(parse_reference("seed"), None);
+    assert_eq!(parse_reference("见面"), None);
+    assert_eq!(
+        find_references("the sensation of light (see also colour)"),
+        vec![Link {
+            target: "colour".to_owned(),
+            kind: LinkKind::See
+        }]
+    );
+    assert_eq!(
+        find_references("A shade of red. See crimson. Cf. scarlet, the brighter one"),
+        vec![
+            Link {
+                target: "crimson".to_owned(),
+                kind: LinkKind::See
+            },
+            Link {
+                target: "scarlet".to_owned(),
+                kind: LinkKind::See
+            },
+        ]
+    );
+    assert!(find_references("I can see the sea").is_empty());
+    assert!(find_references("to see something clearly; see through a trick").is_empty());
+
+    let redirect = Def {
+        related: Some(vec!["(= colour)".to_owned()]),
+        ..Default::default()
+    };
+    assert!(is_redirect(&redirect));
+    let full = Def {
+        EN: Some("the quality of an object, see hue".to_owned()),
+        ..Default::default()
+    };
+    assert!(!is_redirect(&full));
+}
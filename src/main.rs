@@ -6,6 +6,7 @@ use std::{
     env,
     os::fd::{AsFd, AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
     path::PathBuf,
+    sync::Mutex,
     thread,
     time::{Duration, Instant},
 };
@@ -14,11 +15,11 @@ use arc_swap::ArcSwap;
 use crossbeam::atomic::AtomicCell;
 use eyeball::Observable;
 use offdictd::{
-    self, AsyncReadExt, DefItemWrapped, Diverge, Offdict,
+    self, AsyncReadExt, Budget, DefItemWrapped, Diverge, Offdict, SearchOptions,
     def_bin::{Def, Example, MaybeString, MaybeStructuredText, Pronunciation, Tip, WrapperDef},
+    index::Index,
     init_db, process_cmd, stat,
     tests::{collect_defs, load_fixture},
-    topk::Strprox,
 };
 use tokio::{
     net::UnixStream,
@@ -56,7 +57,26 @@ use anyhow::{Result, anyhow};
 use hoverpanel::prelude::*;
 
 static START_AS_DEBUG: bool = false;
+/// Input starting with this looks up headwords by meaning
+static REVERSE_PREFIX: &str = "?";
 static RECENT_QUERY: AtomicCell<Option<Instant>> = AtomicCell::new(None);
+/// Top result of the last query and its dictionaries, counted as looked up once the panel shows it
+static RECENT_HIT: Mutex<Option<(String, Vec<String>)>> = Mutex::new(None);
+/// The newest lookup, compared and updated under one lock so an older one never overwrites the panel
+static LATEST: Mutex<Latest> = Mutex::new(Latest {
+    generation: 0,
+    input: None,
+});
+/// Selections are cut to this many chars, a whole page selected would take long to split
+static MAX_SELECTION: usize = 1000;
+/// Headwords in the dropdown under the input
+static MAX_COMPLETIONS: usize = 8;
+
+struct Latest {
+    generation: u64,
+    /// Shown or being looked up, cleared when the lookup fails so the same input retries
+    input: Option<String>,
+}
 
 struct GlobalConfig {
     /// The amount of time you can dither to decide whether you want the panel to show up
@@ -71,7 +91,7 @@ fn main() -> Result<()> {
 
     let db_path = env::current_dir()?.join("./data");
     if has_args {
-        process_cmd(|| {
+        process_cmd(&db_path.clone(), || {
             let db = init_db(db_path.clone())?;
             db.load_index(db_path)?;
             Ok(db)
@@ -102,12 +122,17 @@ fn main() -> Result<()> {
     tracing::info!("logger set up");
 
     let query_rx = ArcSw::from(ArcSwap::from_pointee(vec![]));
-    let dict: ArcSw<Option<Offdict<Strprox>>> = ArcSw::from(ArcSwap::from_pointee(None));
+    let dict: ArcSw<Option<Offdict<Index>>> = ArcSw::from(ArcSwap::from_pointee(None));
     let dict2 = dict.clone();
+    let dict3 = dict.clone();
     let query_rx2 = query_rx.clone();
     let (wsx, mut wrx) = mpsc::unbounded_channel::<String>();
     let wsx2 = wsx.clone();
     let wsx3 = wsx.clone();
+    let completions = ArcSw::from(ArcSwap::from_pointee(vec![]));
+    let completions2 = completions.clone();
+    let dict4 = dict.clone();
+    let (csx, mut crx) = mpsc::unbounded_channel::<String>();
 
     let (sx, mut evrx, wayland) = WgpuLayerShellApp::new(
         opts,
@@ -116,7 +141,7 @@ fn main() -> Result<()> {
             let defs: Vec<Def> = defs.into_iter().map(|x| x.normalize_def().into()).collect();
             let wrapped = collect_defs(defs);
 
-            let mut dict_load = Offdict::<Strprox>::open_db(db_path.clone())?;
+            let mut dict_load = Offdict::<Index>::open_db(db_path.clone())?;
             dict_load.load_index(db_path)?;
             dict.store(Some(dict_load).into());
 
@@ -128,8 +153,10 @@ fn main() -> Result<()> {
                 stat: None,
                 debug_view: START_AS_DEBUG,
                 query: query_rx,
+                completions,
                 text: String::new(),
                 wsx: wsx2,
+                csx,
                 evrx,
                 bg_opacity: 1.,
                 us: UIState {
@@ -145,6 +172,7 @@ fn main() -> Result<()> {
 
     let msg2 = sx.clone();
     let msg3 = sx.clone();
+    let msg4 = sx.clone();
     use futures::StreamExt;
     use wayland::async_bincode::tokio::*;
 
@@ -169,11 +197,10 @@ fn main() -> Result<()> {
                                 let parse = String::from_utf8(buf);
                                 if let Ok(stx) = parse {
                                     let len = stx.len();
-                                    if len < 20 {
-                                        wsx3.send(stx.clone()).unwrap();
-                                        let stx: String = stx.chars().take(10).collect();
-                                        warn!(len = len, "select {}", stx);
-                                    }
+                                    let stx: String = stx.chars().take(MAX_SELECTION).collect();
+                                    wsx3.send(stx.clone()).unwrap();
+                                    let stx: String = stx.chars().take(10).collect();
+                                    warn!(len = len, "select {}", stx);
                                 }
                             }
                         }
@@ -183,53 +210,93 @@ fn main() -> Result<()> {
                 aok(())
             });
             rt.spawn(async move {
-                let mut last_string = None;
+                // Budget of the lookup in flight, cancelled when newer input arrives
+                let mut running: Option<Budget> = None;
                 loop {
-                    if let Some(stx) = wrx.recv().await {
-                        info!("query {:?}", &stx);
-                        if last_string
-                            .as_ref()
-                            .map(|k: &String| k.as_str() == stx.as_str())
-                            .unwrap_or_default()
-                        {
+                    let Some(mut stx) = wrx.recv().await else {
+                        error!("new word recver stopped");
+                        break;
+                    };
+                    // Input queued meanwhile is superseded by the last of it
+                    while let Ok(newer) = wrx.try_recv() {
+                        stx = newer;
+                    }
+                    info!("query {:?}", &stx);
+                    let dict = dict2.load_full();
+                    if dict.is_none() {
+                        continue;
+                    }
+                    let generation = {
+                        let mut latest = LATEST.lock().unwrap();
+                        if latest.input.as_deref() == Some(stx.as_str()) {
                             continue;
                         }
-                        let dict = dict2.load();
-                        if let Some(ref dict) = **dict {
-                            let dict: &Offdict<Strprox> = dict;
-                            RECENT_QUERY.store(Some(Instant::now()));
-
-                            let rx = dict.search(&stx, 5, false)?;
-                            info!("searched {} with {} results", &stx, rx.len());
-                            let mut new_rx = Vec::new();
-
-                            for per_word in rx {
-                                let mut top = SectionTop {
-                                    title_l1: per_word.word,
-                                    sections: vec![],
-                                };
-                                // L1: word
-                                for (dict, de) in per_word.items {
-                                    let mut sec = SectionsR::default();
-                                    sec.title_l2 = Some(dict.clone());
-                                    let mut ctx: LayerContext<'_> = LayerContext {
-                                        top: &mut top,
-                                        l2: &mut sec,
-                                    };
-                                    render_def(de, &mut ctx, 0);
-                                    top.sections.push_dedup(sec);
+                        latest.generation += 1;
+                        latest.input = Some(stx.clone());
+                        latest.generation
+                    };
+                    RECENT_QUERY.store(Some(Instant::now()));
+                    if let Some(superseded) = running.replace(Budget::default()) {
+                        superseded.cancel();
+                    }
+                    let budget = running.clone().unwrap();
+                    let (query_rx, msg) = (query_rx2.clone(), msg2.clone());
+                    tokio::task::spawn_blocking(move || {
+                        let Some(ref dict) = *dict else {
+                            return;
+                        };
+                        let found = lookup(dict, &stx, &budget);
+                        let mut latest = LATEST.lock().unwrap();
+                        // A newer lookup owns the panel now
+                        if latest.generation != generation {
+                            return;
+                        }
+                        match found {
+                            Ok((new_rx, hit)) => {
+                                *RECENT_HIT.lock().unwrap() = hit;
+                                query_rx.store(new_rx.into());
+                                if let Err(e) = msg.send(Msg::Repaint) {
+                                    warn!("repaint not sent: {}", e);
                                 }
-                                new_rx.push_dedup(top);
                             }
-
-                            query_rx2.store(new_rx.into());
-                            msg2.send(Msg::Repaint)?;
-                            last_string = Some(stx);
+                            Err(e) => {
+                                latest.input = None;
+                                warn!("lookup of {} failed: {}", stx, e);
+                            }
                         }
-                    } else {
-                        error!("new word recver stopped");
+                    });
+                }
+                anyhow::Ok(())
+            });
+            // Completions while typing, off the render thread
+            rt.spawn(async move {
+                loop {
+                    let Some(mut prefix) = crx.recv().await else {
+                        error!("completion recver stopped");
                         break;
+                    };
+                    while let Ok(newer) = crx.try_recv() {
+                        prefix = newer;
                     }
+                    let dict = dict4.load_full();
+                    let (completions, msg) = (completions2.clone(), msg4.clone());
+                    // Awaited, so an older prefix never overwrites a newer one
+                    tokio::task::spawn_blocking(move || {
+                        let found = match &*dict {
+                            Some(dict) => {
+                                dict.complete(&prefix, MAX_COMPLETIONS).unwrap_or_else(|e| {
+                                    warn!("completion of {} failed: {}", prefix, e);
+                                    vec![]
+                                })
+                            }
+                            None => vec![],
+                        };
+                        completions.store(found.into());
+                        if let Err(e) = msg.send(Msg::Repaint) {
+                            warn!("repaint not sent: {}", e);
+                        }
+                    })
+                    .await?;
                 }
                 anyhow::Ok(())
             });
@@ -258,6 +325,13 @@ fn main() -> Result<()> {
                                 }
                                 if allow_show {
                                     used = recent;
+                                    if let (Some((word, dicts)), Some(dict)) =
+                                        (RECENT_HIT.lock().unwrap().take(), &**dict3.load())
+                                    {
+                                        if let Err(e) = dict.record_lookup(&word, &dicts) {
+                                            warn!("lookup history not saved: {}", e);
+                                        }
+                                    }
                                 }
                                 match ges.kind {
                                     Kind::Taps(TapDist::First(_)) => {
@@ -303,16 +377,20 @@ fn main() -> Result<()> {
 struct HoverPanelApp {
     ui: MsgQueue,
     /// allows for loading rocksdb after ui is shown
-    dict: ArcSw<Option<Offdict<Strprox>>>,
+    dict: ArcSw<Option<Offdict<Index>>>,
     search: Vec<DefItemWrapped>,
     status: SearchStatus,
     stat: Option<stat>,
     debug_view: bool,
     /// results from last query
     query: ArcSw<Vec<SectionTop>>,
+    /// Headwords starting with the input, shown under it while typing
+    completions: ArcSw<Vec<String>>,
     /// current input
     text: String,
     wsx: UnboundedSender<String>,
+    /// Prefixes to complete, the results arrive in `completions`
+    csx: UnboundedSender<String>,
     evrx: EvRx,
     /// Opacity for base window
     bg_opacity: f32,
@@ -414,6 +492,8 @@ impl App for HoverPanelApp {
 struct SectionTop {
     /// word string, or source name depending on grouping
     title_l1: String,
+    /// Why a fuzzy or phonetic result matched, shown dimmed with this on hover
+    approx: Option<String>,
     sections: Vec<SectionsR>,
 }
 
@@ -462,9 +542,20 @@ enum SectionT {
     Info {
         text: MaybeStructuredText,
     },
+    /// A sense found by reverse lookup, with byte ranges to highlight
+    Match {
+        text: String,
+        spans: Vec<(usize, usize)>,
+    },
 }
 
 impl HoverPanelApp {
+    /// Hides the dropdown now, and once completions still on their way arrive
+    fn clear_completions(&self) {
+        self.completions.store(Default::default());
+        let _ = self.csx.send(String::new());
+    }
+
     /// Must be generic to actual storage
     fn render_items(&self, mut render: impl FnMut(SectionTop)) {
         let read = self.query.load();
@@ -595,9 +686,18 @@ impl HoverPanelApp {
                                         })
                                         .show(ui, |ui| {
                                             ui.set_width(win.width() - 10.);
-                                            ui.label(
-                                                RichText::new(top.title_l1).color(Color32::WHITE),
-                                            );
+                                            if let Some(why) = top.approx {
+                                                ui.label(
+                                                    RichText::new(top.title_l1)
+                                                        .color(Color32::GRAY),
+                                                )
+                                                .on_hover_text(why);
+                                            } else {
+                                                ui.label(
+                                                    RichText::new(top.title_l1)
+                                                        .color(Color32::WHITE),
+                                                );
+                                            }
                                             ui.spacing();
 
                                             for sec2 in top.sections {
@@ -681,8 +781,37 @@ impl HoverPanelApp {
                             let text = text.ui(ui);
                             if text.changed() {
                                 info!("input = {}", self.text);
+                                // Only headwords while typing, the lookup once one is picked or on enter
+                                let _ = self.csx.send(self.text.clone());
+                            }
+                            if text.lost_focus() && ui.input(|ip| ip.key_pressed(Key::Enter)) {
+                                self.clear_completions();
                                 let _ = self.wsx.send(self.text.clone());
                             }
+                            let completions = self.completions.load_full();
+                            if !completions.is_empty() {
+                                let mut picked = None;
+                                egui::Area::new(egui::Id::new("completions"))
+                                    .order(egui::Order::Foreground)
+                                    .fixed_pos(text.rect.left_bottom())
+                                    .show(ctx, |ui| {
+                                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                            ui.set_width(text.rect.width());
+                                            for c in completions.iter() {
+                                                let item =
+                                                    egui::Button::new(c.as_str()).frame(false);
+                                                if ui.add(item).clicked() {
+                                                    picked = Some(c.clone());
+                                                }
+                                            }
+                                        });
+                                    });
+                                if let Some(c) = picked {
+                                    self.text = c;
+                                    self.clear_completions();
+                                    let _ = self.wsx.send(self.text.clone());
+                                }
+                            }
                             if true {
                                 if ui.button("exit").clicked() {
                                     self.ui.send(Msg::Exit).unwrap();
@@ -759,6 +888,30 @@ impl HoverPanelApp {
                         ui.label(tn);
                     }
                 }
+                SectionT::Match { text, spans } => {
+                    let plain = egui::TextFormat {
+                        color: Color32::WHITE.gamma_multiply(0.8),
+                        ..Default::default()
+                    };
+                    let hit = egui::TextFormat {
+                        color: Color32::YELLOW,
+                        ..Default::default()
+                    };
+                    let mut job = egui::text::LayoutJob::default();
+                    let mut last = 0;
+                    for (a, b) in spans {
+                        job.append(&text[last..a], 0., plain.clone());
+                        job.append(&text[a..b], 0., hit.clone());
+                        last = b;
+                    }
+                    job.append(&text[last..], 0., plain);
+                    ui.label(job);
+                }
+                SectionT::Related { text } => {
+                    for tn in text.into_iter() {
+                        ui.label(RichText::new(tn).color(Color32::LIGHT_BLUE));
+                    }
+                }
                 SectionT::Tip { text } => {
                     for tn in text.into_iter() {
                         match tn {
@@ -1068,6 +1221,126 @@ impl<T: Eq> VecExt<T> for Vec<T> {
     }
 }
 
+/// Sections for the panel, and the top hit to count once the panel shows it
+fn lookup(
+    dict: &Offdict<Index>,
+    stx: &str,
+    budget: &Budget,
+) -> Result<(Vec<SectionTop>, Option<(String, Vec<String>)>)> {
+    let opts = SearchOptions {
+        budget: budget.clone(),
+        ..SearchOptions::top(5)
+    };
+    let hit;
+    let mut new_rx = Vec::new();
+    // Once, for both the check and the lookup
+    let segmented = if stx.starts_with(REVERSE_PREFIX) {
+        vec![]
+    } else {
+        dict.segment(stx)
+    };
+    if let Some(meaning) = stx.strip_prefix(REVERSE_PREFIX) {
+        // From a meaning to headwords, with the matching sense
+        let hits = dict.fulltext(meaning.trim(), &opts)?.value;
+        hit = hits.first().map(|h| (h.word.clone(), vec![h.dict.clone()]));
+        for hit in hits {
+            let mut sec = SectionsR::default();
+            sec.title_l2 = Some(hit.dict);
+            sec.content.push(SectionT::Match {
+                text: hit.text,
+                spans: hit.spans,
+            });
+            new_rx.push_dedup(SectionTop {
+                title_l1: hit.word,
+                approx: None,
+                sections: vec![sec],
+            });
+        }
+    } else if segmented.len() > 1 && dict.normalized_matches(stx)?.is_empty() {
+        // A sentence, or words that aren't a headword together, one by one
+        let units = dict.lookup_units_of(
+            segmented,
+            &SearchOptions {
+                num: 20,
+                ..opts.clone()
+            },
+        )?;
+        info!("split {} into {} results", stx, units.len());
+        hit = units
+            .first()
+            .map(|(_, w)| (w.word.clone(), w.items.keys().cloned().collect()));
+
+        for (unit, per_word) in units {
+            let title = match &per_word.label {
+                Some(label) => label.clone(),
+                None if unit.text.to_lowercase() != per_word.word.to_lowercase() => {
+                    format!("{} → {}", unit.text, per_word.word)
+                }
+                None => per_word.word.clone(),
+            };
+            new_rx.push_dedup(word_top(per_word, title));
+        }
+    } else {
+        let (mut rx, suggested) = offdictd::search_or_suggest(dict, stx, &opts)?;
+        info!("searched {} with {} results", stx, rx.value.len());
+        if let Some(first) = suggested.first() {
+            // Nothing close, the likeliest correction instead of a wrong definition
+            let mut title = format!("Did you mean {}?", first);
+            if suggested.len() > 1 {
+                title.push_str(&format!(" Or {}", suggested[1..].join(", ")));
+            }
+            new_rx.push_dedup(SectionTop {
+                title_l1: title,
+                approx: None,
+                sections: vec![],
+            });
+            rx = dict.search(
+                first,
+                &SearchOptions {
+                    num: 1,
+                    ..opts.clone()
+                },
+            )?;
+        }
+        hit = rx
+            .value
+            .first()
+            .map(|w| (w.word.clone(), w.items.keys().cloned().collect()));
+
+        for per_word in rx.value {
+            // "running → run" when it came from the lemma
+            let title = per_word.label.clone().unwrap_or(per_word.word.clone());
+            new_rx.push_dedup(word_top(per_word, title));
+        }
+    }
+    Ok((new_rx, hit))
+}
+
+/// One headword with a section per dictionary
+fn word_top(per_word: DefItemWrapped, title: String) -> SectionTop {
+    let mut top = SectionTop {
+        title_l1: title,
+        approx: per_word
+            .matched
+            .as_ref()
+            .filter(|m| m.is_approximate())
+            .map(|m| m.to_string()),
+        sections: vec![],
+    };
+    // L1: word
+    for (dict, de) in per_word.items {
+        let mut sec = SectionsR::default();
+        sec.title_l2 = Some(dict.clone());
+        let mut ctx: LayerContext<'_> = LayerContext {
+            top: &mut top,
+            l2: &mut sec,
+        };
+        render_def(de, &mut ctx, 0);
+        top.sections.push_dedup(sec);
+    }
+    top
+}
+
 fn render_def(de: Def, ctx: &mut LayerContext, depth: u32) {
     if let Some(pn) = de.pronunciation {
         info!("pronunciation {:?} {:?}", &de.word, &ctx.l2.kind);
@@ -1084,6 +1357,11 @@ fn render_def(de: Def, ctx: &mut LayerContext, depth: u32) {
             text: MaybeStructuredText::Str(inf),
         });
     }
+    if let Some(rel) = de.related {
+        ctx.l2.content.push_dedup(SectionT::Related {
+            text: MaybeStructuredText::Vec(rel.into_iter().map(Option::Some).collect()),
+        });
+    }
     if de.CN.is_some() || de.EN.is_some() {
         ctx.l2.content.push_dedup(SectionT::Explain {
             en: de.EN.into(),